@@ -0,0 +1,394 @@
+//! Typed entry point to build and install the C-API of a crate, usable
+//! without going through the command line.
+//!
+//! ```no_run
+//! use cargo_c::api::CApiBuild;
+//! use cargo_c::metadata::LibraryType;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let output = CApiBuild::new("Cargo.toml")
+//!     .release(true)
+//!     .prefix("/usr")
+//!     .library_types(&[LibraryType::Cdylib])
+//!     .build()?;
+//!
+//! for pkg in output.packages.iter() {
+//!     println!("{:?}", pkg.build_targets);
+//! }
+//!
+//! output.install()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use cargo::core::compiler::{BuildConfig, MessageFormat};
+use cargo::core::Workspace;
+use cargo::ops;
+use cargo::util::command_prelude::{ArgMatches, ArgMatchesExt, CompileMode, ProfileChecking};
+use cargo::util::interning::InternedString;
+use cargo::Config;
+
+use crate::build::{cbuild, CPackage};
+use crate::header::{cheader, HeaderMode};
+use crate::info::{cinfo, CInfo};
+use crate::install::{cinstall, InstallOptions};
+use crate::metadata::LibraryType;
+use crate::pkg_config_gen::check_pc_file;
+use crate::target::Target;
+
+/// Builder describing how to build the C-API of one or more packages
+#[derive(Debug, Clone)]
+pub struct CApiBuild {
+    pub(crate) manifest_path: PathBuf,
+    pub(crate) packages: Vec<String>,
+    pub(crate) workspace: bool,
+    pub(crate) exclude: Vec<String>,
+    pub(crate) target: Option<String>,
+    pub(crate) profile: String,
+    pub(crate) features: Vec<String>,
+    pub(crate) all_features: bool,
+    pub(crate) no_default_features: bool,
+    pub(crate) jobs: Option<u32>,
+    pub(crate) library_types: Option<Vec<LibraryType>>,
+    pub(crate) install: InstallOptions,
+    pub(crate) dlltool: Option<PathBuf>,
+    pub(crate) out_dir: Option<PathBuf>,
+    pub(crate) json_messages: bool,
+    pub(crate) build_plan: bool,
+    /// Command line the options were parsed from, if any
+    pub(crate) args: Option<ArgMatches<'static>>,
+}
+
+impl CApiBuild {
+    /// Build the package at `manifest_path` in the `dev` profile for the
+    /// host, producing both static and shared libraries.
+    pub fn new<P: AsRef<Path>>(manifest_path: P) -> Self {
+        CApiBuild {
+            manifest_path: manifest_path.as_ref().to_path_buf(),
+            packages: Vec::new(),
+            workspace: false,
+            exclude: Vec::new(),
+            target: None,
+            profile: "dev".into(),
+            features: Vec::new(),
+            all_features: false,
+            no_default_features: false,
+            jobs: None,
+            library_types: None,
+            install: InstallOptions::default(),
+            dlltool: None,
+            out_dir: None,
+            json_messages: false,
+            build_plan: false,
+            args: None,
+        }
+    }
+
+    /// Map the command line arguments of `cbuild`, `cinstall` and `capi`
+    ///
+    /// The compile options are then built by cargo from the arguments
+    /// themselves, as `cargo build` would.
+    pub fn from_args(config: &Config, args: &ArgMatches<'static>) -> anyhow::Result<Self> {
+        let path = |key: &str| args.value_of_os(key).map(PathBuf::from);
+
+        let profile = match args.value_of("profile") {
+            Some(profile) => profile.to_owned(),
+            None if args.is_present("release") => "release".into(),
+            None => "dev".into(),
+        };
+
+        let library_types = match args.values_of("library-type") {
+            Some(types) => Some(
+                types
+                    .map(|t| match t.to_lowercase().as_str() {
+                        "cdylib" => Ok(LibraryType::Cdylib),
+                        "staticlib" => Ok(LibraryType::Staticlib),
+                        _ => Err(anyhow::anyhow!(
+                            "Unknown library type `{}`, expected `cdylib` or `staticlib`",
+                            t
+                        )),
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+
+        let json_messages = args.values_of("message-format").map_or(false, |mut v| {
+            v.any(|f| f.to_lowercase().starts_with("json"))
+        });
+
+        Ok(CApiBuild {
+            manifest_path: args.root_manifest(config)?,
+            packages: args._values_of("package"),
+            workspace: args.is_present("workspace") || args.is_present("all"),
+            exclude: args._values_of("exclude"),
+            target: args.target(),
+            profile,
+            features: args._values_of("features"),
+            all_features: args.is_present("all-features"),
+            no_default_features: args.is_present("no-default-features"),
+            jobs: args.jobs()?,
+            library_types,
+            install: InstallOptions {
+                destdir: path("destdir"),
+                prefix: path("prefix"),
+                exec_prefix: path("exec-prefix"),
+                libdir: path("libdir"),
+                includedir: path("includedir"),
+                bindir: path("bindir"),
+                pkgconfigdir: path("pkgconfigdir"),
+            },
+            dlltool: path("dlltool"),
+            out_dir: args.value_of_path("out-dir", config),
+            json_messages,
+            build_plan: args.is_present("build-plan"),
+            args: Some(args.clone()),
+        })
+    }
+
+    /// Select a package of the workspace, may be called multiple times
+    pub fn package<S: Into<String>>(mut self, name: S) -> Self {
+        self.packages.push(name.into());
+        self
+    }
+
+    /// Select all the workspace members providing a `package.metadata.capi`
+    /// section
+    pub fn workspace(mut self, workspace: bool) -> Self {
+        self.workspace = workspace;
+        self
+    }
+
+    /// Exclude a package when building the whole workspace
+    pub fn exclude<S: Into<String>>(mut self, name: S) -> Self {
+        self.exclude.push(name.into());
+        self
+    }
+
+    /// Build for the target triple
+    pub fn target<S: Into<String>>(mut self, target: S) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Build with the `release` profile instead of the `dev` one
+    pub fn release(mut self, release: bool) -> Self {
+        self.profile = if release { "release" } else { "dev" }.into();
+        self
+    }
+
+    /// Build with the named profile
+    pub fn profile<S: Into<String>>(mut self, profile: S) -> Self {
+        self.profile = profile.into();
+        self
+    }
+
+    /// Enable a feature, may be called multiple times
+    pub fn feature<S: Into<String>>(mut self, feature: S) -> Self {
+        self.features.push(feature.into());
+        self
+    }
+
+    pub fn all_features(mut self, all_features: bool) -> Self {
+        self.all_features = all_features;
+        self
+    }
+
+    pub fn no_default_features(mut self, no_default_features: bool) -> Self {
+        self.no_default_features = no_default_features;
+        self
+    }
+
+    /// Number of parallel jobs, defaults to the number of CPUs
+    pub fn jobs(mut self, jobs: u32) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Library types to build, overriding the manifest settings
+    pub fn library_types(mut self, types: &[LibraryType]) -> Self {
+        self.library_types = Some(types.to_vec());
+        self
+    }
+
+    /// Directory the install paths are appended to
+    pub fn destdir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.install.destdir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn prefix<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.install.prefix = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn exec_prefix<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.install.exec_prefix = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn libdir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.install.libdir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn includedir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.install.includedir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn bindir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.install.bindir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn pkgconfigdir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.install.pkgconfigdir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// `dlltool` used to build the import library on windows-gnu targets
+    pub fn dlltool<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.dlltool = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Copy the final artifacts to this directory
+    pub fn out_dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.out_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Emit machine-readable JSON messages on stdout
+    pub fn json_messages(mut self, json_messages: bool) -> Self {
+        self.json_messages = json_messages;
+        self
+    }
+
+    pub(crate) fn package_spec(&self) -> anyhow::Result<ops::Packages> {
+        ops::Packages::from_flags(self.workspace, self.exclude.clone(), self.packages.clone())
+    }
+
+    pub(crate) fn compile_options(
+        &self,
+        config: &Config,
+        ws: &Workspace,
+    ) -> anyhow::Result<ops::CompileOptions> {
+        if let Some(args) = self.args.as_ref() {
+            return args.compile_options(
+                config,
+                CompileMode::Build,
+                Some(ws),
+                ProfileChecking::Checked,
+            );
+        }
+
+        let mut compile_opts = ops::CompileOptions::new(config, CompileMode::Build)?;
+
+        let mut build_config =
+            BuildConfig::new(config, self.jobs, &self.target, CompileMode::Build)?;
+        build_config.requested_profile = InternedString::new(&self.profile);
+        if self.json_messages {
+            build_config.message_format = MessageFormat::Json {
+                render_diagnostics: false,
+                short: false,
+                ansi: false,
+            };
+        }
+        if self.build_plan {
+            config
+                .cli_unstable()
+                .fail_if_stable_opt("--build-plan", 5579)?;
+            build_config.build_plan = true;
+        }
+
+        compile_opts.build_config = build_config;
+        compile_opts.features = self.features.clone();
+        compile_opts.all_features = self.all_features;
+        compile_opts.no_default_features = self.no_default_features;
+        compile_opts.spec = self.package_spec()?;
+
+        Ok(compile_opts)
+    }
+
+    fn workspace_with_config<'cfg>(&self, config: &'cfg Config) -> anyhow::Result<Workspace<'cfg>> {
+        let manifest_path = if self.manifest_path.is_absolute() {
+            self.manifest_path.clone()
+        } else {
+            config.cwd().join(&self.manifest_path)
+        };
+
+        Workspace::new(&manifest_path, config)
+    }
+
+    /// Build using the default cargo configuration
+    pub fn build(&self) -> anyhow::Result<CApiOutput> {
+        let config = Config::default()?;
+        self.build_with_config(&config)
+    }
+
+    /// Build the selected packages
+    pub fn build_with_config(&self, config: &Config) -> anyhow::Result<CApiOutput> {
+        let mut ws = self.workspace_with_config(config)?;
+        let target = Target::new(self.target.as_ref())?;
+        let packages = cbuild(&mut ws, config, self, &target)?;
+
+        Ok(CApiOutput { target, packages })
+    }
+
+    /// Resolve the configuration without building anything
+    pub fn info_with_config(&self, config: &Config) -> anyhow::Result<CInfo> {
+        let mut ws = self.workspace_with_config(config)?;
+        cinfo(&mut ws, config, self)
+    }
+
+    /// Compare the generated headers with the ones committed in `assets`,
+    /// or update them
+    pub fn header_with_config(&self, config: &Config, mode: HeaderMode) -> anyhow::Result<()> {
+        let mut ws = self.workspace_with_config(config)?;
+        cheader(&mut ws, config, self, mode)
+    }
+}
+
+/// Artifacts produced by [`CApiBuild`] and the paths to install them to
+#[derive(Debug)]
+pub struct CApiOutput {
+    pub target: Target,
+    pub packages: Vec<CPackage>,
+}
+
+impl CApiOutput {
+    /// Install using the default cargo configuration
+    pub fn install(&self) -> anyhow::Result<()> {
+        let config = Config::default()?;
+        self.install_with_config(&config)
+    }
+
+    /// Parse and validate the pkg-config file of every package
+    pub fn check_pc_with_config(&self, config: &Config) -> anyhow::Result<()> {
+        for pkg in self.packages.iter() {
+            let pc = &pkg.build_targets.pc;
+            config.shell().status("Checking", pc.display())?;
+            check_pc_file(pc)?;
+        }
+
+        Ok(())
+    }
+
+    /// Install the artifacts of every package
+    pub fn install_with_config(&self, config: &Config) -> anyhow::Result<()> {
+        for pkg in self.packages.iter() {
+            cinstall(
+                config,
+                &self.target,
+                &pkg.capi_config,
+                &pkg.build_targets,
+                &pkg.install_paths,
+            )?;
+        }
+
+        Ok(())
+    }
+}
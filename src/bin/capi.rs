@@ -1,40 +1,80 @@
-use cargo_c::build::{cbuild, config_configure};
+use cargo_c::api::CApiBuild;
+use cargo_c::build::config_configure;
 use cargo_c::cli::subcommand_cli;
-use cargo_c::install::cinstall;
-use cargo_c::target::Target;
+use cargo_c::header::HeaderMode;
+use cargo_c::metadata;
 
+use cargo::core::Shell;
 use cargo::util::command_prelude::opt;
-use cargo::util::command_prelude::ArgMatchesExt;
 use cargo::CliResult;
 use cargo::Config;
 
 use structopt::clap::*;
 
-fn main() -> CliResult {
-    let mut config = Config::default()?;
-
+fn run(config: &mut Config) -> CliResult {
     let cli_build = subcommand_cli("build", "Build the crate C-API");
     let cli_install = subcommand_cli("install", "Install the crate C-API");
+    let cli_info = subcommand_cli(
+        "info",
+        "Print the resolved C-API configuration without building",
+    )
+    .arg(
+        opt("format", "Output format")
+            .value_name("FORMAT")
+            .possible_values(&["json", "toml"])
+            .default_value("json"),
+    );
+    let cli_header = subcommand_cli(
+        "header",
+        "Compare the generated header with the one in the assets directory",
+    )
+    .arg(opt("check", "Print the differences and fail if any (default)").conflicts_with("update"))
+    .arg(opt("update", "Rewrite the header in the assets directory"));
+    let cli_pc = subcommand_cli("pc", "Build and validate the pkg-config file").arg(opt(
+        "check",
+        "Parse and validate the generated pkg-config file (default)",
+    ));
 
-    let mut app = app_from_crate!()
-        .settings(&[
-            AppSettings::UnifiedHelpMessage,
-            AppSettings::DeriveDisplayOrder,
-            AppSettings::VersionlessSubcommands,
-        ])
-        .subcommand(
-            SubCommand::with_name("capi")
-                .about("Build or install the crate C-API")
-                .arg(opt("version", "Print version info and exit").short("V"))
-                .subcommand(cli_build)
-                .subcommand(cli_install),
-        );
+    let mut app =
+        app_from_crate!()
+            .settings(&[
+                AppSettings::UnifiedHelpMessage,
+                AppSettings::DeriveDisplayOrder,
+                AppSettings::VersionlessSubcommands,
+            ])
+            .subcommand(
+                SubCommand::with_name("capi")
+                    .about("Build or install the crate C-API")
+                    .arg(opt("version", "Print version info and exit").short("V"))
+                    .subcommand(cli_build)
+                    .subcommand(cli_install)
+                    .subcommand(cli_info)
+                    .subcommand(cli_header)
+                    .subcommand(cli_pc)
+                    .subcommand(SubCommand::with_name("schema").about(
+                        "Print the JSON schema of the package.metadata.capi manifest section",
+                    )),
+            );
 
     let args = app.clone().get_matches();
 
     let (cmd, subcommand_args) = match args.subcommand() {
         ("capi", Some(args)) => match args.subcommand() {
-            (cmd, Some(args)) if cmd == "build" || cmd == "install" => (cmd, args),
+            ("schema", Some(_)) => {
+                let schema = serde_json::to_string_pretty(&metadata::schema())
+                    .map_err(anyhow::Error::from)?;
+                println!("{}", schema);
+                return Ok(());
+            }
+            (cmd, Some(args))
+                if cmd == "build"
+                    || cmd == "install"
+                    || cmd == "info"
+                    || cmd == "header"
+                    || cmd == "pc" =>
+            {
+                (cmd, args)
+            }
             _ => {
                 // No subcommand provided.
                 app.print_help()?;
@@ -52,21 +92,47 @@ fn main() -> CliResult {
         return Ok(());
     }
 
-    config_configure(&mut config, subcommand_args)?;
+    config_configure(config, subcommand_args)?;
 
-    let mut ws = subcommand_args.workspace(&config)?;
+    let build = CApiBuild::from_args(config, subcommand_args)?;
 
-    let (build_targets, install_paths, capi_config) = cbuild(&mut ws, &config, &subcommand_args)?;
+    if cmd == "info" {
+        let info = build.info_with_config(config)?;
+        let format = subcommand_args.value_of("format").unwrap_or("json");
+        println!("{}", info.render(format)?);
+        return Ok(());
+    }
+
+    if cmd == "header" {
+        let mode = if subcommand_args.is_present("update") {
+            HeaderMode::Update
+        } else {
+            HeaderMode::Check
+        };
+        build.header_with_config(config, mode)?;
+        return Ok(());
+    }
+
+    let output = build.build_with_config(config)?;
+
+    if cmd == "pc" {
+        output.check_pc_with_config(config)?;
+    }
 
     if cmd == "install" {
-        cinstall(
-            &ws,
-            &Target::new(subcommand_args.target())?,
-            &capi_config,
-            build_targets,
-            install_paths,
-        )?;
+        output.install_with_config(config)?;
     }
 
     Ok(())
 }
+
+fn main() {
+    let mut config = match Config::default() {
+        Ok(config) => config,
+        Err(e) => cargo::exit_with_error(e.into(), &mut Shell::new()),
+    };
+
+    if let Err(e) = run(&mut config) {
+        cargo::exit_with_error(e, &mut *config.shell())
+    }
+}
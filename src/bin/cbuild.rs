@@ -1,15 +1,14 @@
-use cargo::util::command_prelude::ArgMatchesExt;
+use cargo::core::Shell;
 use cargo::CliResult;
 use cargo::Config;
 
-use cargo_c::build::*;
+use cargo_c::api::CApiBuild;
+use cargo_c::build::config_configure;
 use cargo_c::cli::subcommand_cli;
 
 use structopt::clap::*;
 
-fn main() -> CliResult {
-    let mut config = Config::default()?;
-
+fn run(config: &mut Config) -> CliResult {
     let subcommand = subcommand_cli("cbuild", "Build the crate C-API");
 
     let mut app = app_from_crate!()
@@ -36,11 +35,20 @@ fn main() -> CliResult {
         return Ok(());
     }
 
-    config_configure(&mut config, subcommand_args)?;
-
-    let mut ws = subcommand_args.workspace(&config)?;
+    config_configure(config, subcommand_args)?;
 
-    let _ = cbuild(&mut ws, &config, &subcommand_args)?;
+    CApiBuild::from_args(config, subcommand_args)?.build_with_config(config)?;
 
     Ok(())
 }
+
+fn main() {
+    let mut config = match Config::default() {
+        Ok(config) => config,
+        Err(e) => cargo::exit_with_error(e.into(), &mut Shell::new()),
+    };
+
+    if let Err(e) = run(&mut config) {
+        cargo::exit_with_error(e, &mut *config.shell())
+    }
+}
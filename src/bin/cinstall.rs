@@ -1,17 +1,14 @@
-use cargo::util::command_prelude::ArgMatchesExt;
+use cargo::core::Shell;
 use cargo::CliResult;
 use cargo::Config;
 
-use cargo_c::build::{cbuild, config_configure};
+use cargo_c::api::CApiBuild;
+use cargo_c::build::config_configure;
 use cargo_c::cli::subcommand_cli;
-use cargo_c::install::cinstall;
-use cargo_c::target::Target;
 
 use structopt::clap::*;
 
-fn main() -> CliResult {
-    let mut config = Config::default()?;
-
+fn run(config: &mut Config) -> CliResult {
     let subcommand = subcommand_cli("cinstall", "Install the crate C-API");
     let mut app = app_from_crate!()
         .settings(&[
@@ -37,19 +34,22 @@ fn main() -> CliResult {
         return Ok(());
     }
 
-    config_configure(&mut config, subcommand_args)?;
+    config_configure(config, subcommand_args)?;
 
-    let mut ws = subcommand_args.workspace(&config)?;
+    CApiBuild::from_args(config, subcommand_args)?
+        .build_with_config(config)?
+        .install_with_config(config)?;
 
-    let (build_targets, install_paths, capi_config) = cbuild(&mut ws, &config, &subcommand_args)?;
+    Ok(())
+}
 
-    cinstall(
-        &ws,
-        &Target::new(subcommand_args.target())?,
-        &capi_config,
-        build_targets,
-        install_paths,
-    )?;
+fn main() {
+    let mut config = match Config::default() {
+        Ok(config) => config,
+        Err(e) => cargo::exit_with_error(e.into(), &mut Shell::new()),
+    };
 
-    Ok(())
+    if let Err(e) = run(&mut config) {
+        cargo::exit_with_error(e, &mut *config.shell())
+    }
 }
@@ -1,120 +1,1042 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use cargo::core::compiler::{CompileMode, DefaultExecutor, Executor, RustcTargetData};
 use cargo::core::profiles::Profiles;
-use cargo::core::{TargetKind, Workspace};
+use cargo::core::resolver::features::FeaturesFor;
+use cargo::core::resolver::{HasDevUnits, ResolveOpts};
+use cargo::core::{Package, PackageId, PackageIdSpec, TargetKind, Workspace};
 use cargo::ops;
-use cargo::util::command_prelude::{ArgMatches, ArgMatchesExt, CompileMode, ProfileChecking};
-use cargo::{CliResult, Config};
+use cargo::util::command_prelude::{ArgMatches, ArgMatchesExt};
+use cargo::util::ProcessBuilder;
+use cargo::{CargoResult, CliResult, Config};
 
+use anyhow::Context;
+use cargo_platform::Cfg;
 use semver::Version;
+use serde_derive::Serialize;
 
+use crate::api::CApiBuild;
 use crate::build_targets::BuildTargets;
+use crate::error::{self, Error};
 use crate::install::InstallPaths;
+use crate::metadata::{self, BuiltinGenerator, HeaderGenerator, LibraryType, VersionSource};
 use crate::pkg_config_gen::PkgConfig;
 use crate::static_libs::get_static_libs_for_target;
 use crate::target;
 
-/// Build the C header
-fn build_include_file(
-    ws: &Workspace,
+/// Uppercase the header name and turn it into a valid C identifier
+fn macro_prefix(header_name: &str) -> String {
+    c_identifier(header_name.trim_end_matches(".h"))
+}
+
+fn c_identifier(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Version macros, emitted after the header include guard
+fn version_macros(config: &HeaderVersionCApiConfig) -> String {
+    let prefix = &config.prefix;
+    let version = &config.version;
+
+    let mut macros = format!(
+        "\n#define {0}_MAJOR {1}\n#define {0}_MINOR {2}\n#define {0}_PATCH {3}\n",
+        prefix, version.major, version.minor, version.patch
+    );
+
+    if config.string {
+        macros += &format!(
+            "#define {}_VERSION_STRING \"{}.{}.{}\"\n",
+            prefix, version.major, version.minor, version.patch
+        );
+    }
+
+    if config.packed {
+        let packed = version.major << 16 | version.minor << 8 | version.patch;
+        macros += &format!("#define {}_VERSION 0x{:06x}\n", prefix, packed);
+    }
+
+    if config.check {
+        macros += &format!(
+            concat!(
+                "#define {0}_CHECK_VERSION(major, minor, patch) \\\n",
+                "    ({0}_MAJOR > (major) || \\\n",
+                "     ({0}_MAJOR == (major) && {0}_MINOR > (minor)) || \\\n",
+                "     ({0}_MAJOR == (major) && {0}_MINOR == (minor) && {0}_PATCH >= (patch)))\n",
+            ),
+            prefix
+        );
+    }
+
+    if config.functions {
+        let prefix = prefix.to_lowercase();
+        macros += &format!(
+            concat!(
+                "\n#ifdef __cplusplus\nextern \"C\" {{\n#endif\n\n",
+                "unsigned int {0}_version_major(void);\n",
+                "unsigned int {0}_version_minor(void);\n",
+                "unsigned int {0}_version_patch(void);\n",
+                "const char *{0}_version_string(void);\n",
+                "\n#ifdef __cplusplus\n}}\n#endif\n",
+            ),
+            prefix
+        );
+    }
+
+    macros
+}
+
+/// Rust source exporting the version query functions
+fn version_functions(config: &HeaderVersionCApiConfig) -> String {
+    let prefix = config.prefix.to_lowercase();
+    let version = &config.version;
+
+    format!(
+        concat!(
+            "// Generated by cargo-c, do not edit.\n\n",
+            "#[no_mangle]\n",
+            "pub extern \"C\" fn {0}_version_major() -> std::os::raw::c_uint {{\n    {1}\n}}\n\n",
+            "#[no_mangle]\n",
+            "pub extern \"C\" fn {0}_version_minor() -> std::os::raw::c_uint {{\n    {2}\n}}\n\n",
+            "#[no_mangle]\n",
+            "pub extern \"C\" fn {0}_version_patch() -> std::os::raw::c_uint {{\n    {3}\n}}\n\n",
+            "#[no_mangle]\n",
+            "pub extern \"C\" fn {0}_version_string() -> *const std::os::raw::c_char {{\n",
+            "    b\"{1}.{2}.{3}\\0\".as_ptr() as *const std::os::raw::c_char\n}}\n",
+        ),
+        prefix, version.major, version.minor, version.patch
+    )
+}
+
+/// Write the source the crate includes under `cfg(cargo_c)` to export the
+/// version functions, it is rewritten only if the version changes to avoid
+/// spurious rebuilds. Nothing is written unless `header.version.functions`
+/// is set.
+pub(crate) fn build_version_functions_file(
     name: &str,
-    version: &Version,
+    header: &HeaderCApiConfig,
     root_output: &PathBuf,
+) -> anyhow::Result<Option<PathBuf>> {
+    if !header.version.functions {
+        return Ok(None);
+    }
+
+    let path = root_output.join(format!("{}-version.rs", name));
+    let source = version_functions(&header.version);
+
+    if std::fs::read_to_string(&path).ok().as_deref() != Some(source.as_str()) {
+        std::fs::create_dir_all(root_output)?;
+        std::fs::write(&path, source)?;
+    }
+
+    Ok(Some(path))
+}
+
+/// Runs rustc as cargo does, passing `CARGO_C_VERSION_RS` to the package
+/// it is built for without touching the environment of the process
+struct VersionFunctionsExecutor {
+    package_id: PackageId,
+    version_rs: Option<PathBuf>,
+}
+
+impl Executor for VersionFunctionsExecutor {
+    fn exec(
+        &self,
+        mut cmd: ProcessBuilder,
+        id: PackageId,
+        target: &cargo::core::Target,
+        mode: CompileMode,
+        on_stdout_line: &mut dyn FnMut(&str) -> CargoResult<()>,
+        on_stderr_line: &mut dyn FnMut(&str) -> CargoResult<()>,
+    ) -> CargoResult<()> {
+        if let Some(version_rs) = self.version_rs.as_ref() {
+            if id == self.package_id {
+                cmd.env("CARGO_C_VERSION_RS", version_rs);
+            }
+        }
+        DefaultExecutor.exec(cmd, id, target, mode, on_stdout_line, on_stderr_line)
+    }
+}
+
+fn feature_macro(prefix: &str, feature: &str) -> String {
+    format!("{}_FEATURE_{}", prefix, c_identifier(feature))
+}
+
+/// `#define` the macros of the enabled features
+fn feature_macros(prefix: &str, features: &BTreeSet<String>) -> String {
+    let mut macros = String::from("\n");
+    for feature in features {
+        macros += &format!("#define {} 1\n", feature_macro(prefix, feature));
+    }
+    macros
+}
+
+/// Target cfgs cbindgen may find in the sources, the ones not matching the
+/// target are mapped to macros that are never defined
+const TARGET_CFGS: &[(&str, &[&str])] = &[
+    ("target_family", &["unix", "windows", "wasm"]),
+    (
+        "target_os",
+        &[
+            "linux",
+            "android",
+            "macos",
+            "ios",
+            "windows",
+            "freebsd",
+            "dragonfly",
+            "netbsd",
+            "openbsd",
+            "solaris",
+            "illumos",
+            "haiku",
+            "fuchsia",
+            "redox",
+            "emscripten",
+            "wasi",
+            "none",
+        ],
+    ),
+    ("target_env", &["gnu", "msvc", "musl", "sgx", "uclibc"]),
+    (
+        "target_arch",
+        &[
+            "x86",
+            "x86_64",
+            "arm",
+            "aarch64",
+            "mips",
+            "mips64",
+            "powerpc",
+            "powerpc64",
+            "riscv64",
+            "s390x",
+            "sparc64",
+            "wasm32",
+        ],
+    ),
+    ("target_pointer_width", &["16", "32", "64"]),
+    ("target_endian", &["little", "big"]),
+];
+
+/// Bare target cfgs, e.g. `#[cfg(windows)]`
+const TARGET_CFG_NAMES: &[&str] = &["unix", "windows"];
+
+fn cfg_macro(prefix: &str, cfg: &str) -> String {
+    format!("{}_CFG_{}", prefix, c_identifier(cfg))
+}
+
+/// Features of `pkg` cargo's resolver enables for the build described by
+/// `compile_opts`, optional dependencies included
+fn enabled_features(
+    ws: &Workspace,
+    pkg: &Package,
+    compile_opts: &ops::CompileOptions,
+) -> anyhow::Result<BTreeSet<String>> {
+    let requested_kind = compile_opts.build_config.requested_kind;
+    let target_data = RustcTargetData::new(ws, requested_kind)?;
+    let opts = ResolveOpts::new(
+        false,
+        &compile_opts.features,
+        compile_opts.all_features,
+        !compile_opts.no_default_features,
+    );
+    let specs = [PackageIdSpec::from_package_id(pkg.package_id())];
+
+    let resolve = ops::resolve_ws_with_opts(
+        ws,
+        &target_data,
+        requested_kind,
+        &opts,
+        &specs,
+        HasDevUnits::No,
+    )?;
+
+    Ok(resolve
+        .resolved_features
+        .activated_features(pkg.package_id(), FeaturesFor::NormalOrDev)
+        .iter()
+        .map(|f| f.to_string())
+        .collect())
+}
+
+/// Features and cfg values the library is built with, fed to cbindgen so
+/// the header matches it
+struct HeaderCfg<'a> {
+    package: String,
+    release: bool,
+    /// Every feature the package declares, optional dependencies included
+    features: BTreeSet<String>,
+    enabled_features: BTreeSet<String>,
+    target: &'a target::Target,
+    /// Passed as `CARGO_C_VERSION_RS` when expanding the crate
+    version_rs: Option<PathBuf>,
+}
+
+impl<'a> HeaderCfg<'a> {
+    fn new(
+        ws: &Workspace,
+        pkg: &Package,
+        compile_opts: &ops::CompileOptions,
+        target: &'a target::Target,
+        version_rs: Option<&Path>,
+    ) -> anyhow::Result<HeaderCfg<'a>> {
+        let features = pkg
+            .summary()
+            .features()
+            .keys()
+            .map(|f| f.to_string())
+            .chain(
+                pkg.dependencies()
+                    .iter()
+                    .filter(|dep| dep.is_optional())
+                    .map(|dep| dep.name_in_toml().to_string()),
+            )
+            .collect();
+        let enabled_features = enabled_features(ws, pkg, compile_opts)?;
+
+        Ok(HeaderCfg {
+            package: pkg.name().to_string(),
+            release: compile_opts.build_config.requested_profile.as_str() == "release",
+            features,
+            enabled_features,
+            target,
+            version_rs: version_rs.map(Path::to_path_buf),
+        })
+    }
+
+    /// Map the `feature = "name"` cfgs to the feature macros so cbindgen
+    /// guards the feature-gated items. The enabled features are left
+    /// unmapped when their macros are not emitted so their items are kept.
+    ///
+    /// The target cfgs not matching the target are mapped to macros that are
+    /// never defined, so their items are left out. `cargo_c` and the matching
+    /// cfgs are set for the library and stay unmapped, as the enabled
+    /// features.
+    fn apply_defines(&self, config: &mut toml::value::Table, prefix: &str, feature_macros: bool) {
+        let defines = table_mut(config, "defines");
+
+        for feature in self.features.iter() {
+            if feature_macros || !self.enabled_features.contains(feature) {
+                defines
+                    .entry(format!("feature = {}", feature))
+                    .or_insert_with(|| feature_macro(prefix, feature).into());
+            }
+        }
+
+        for (key, values) in TARGET_CFGS.iter() {
+            for value in values.iter() {
+                let cfg = Cfg::KeyPair(key.to_string(), value.to_string());
+                if !self.target.cfg.contains(&cfg) {
+                    defines
+                        .entry(format!("{} = {}", key, value))
+                        .or_insert_with(|| cfg_macro(prefix, &format!("{}_{}", key, value)).into());
+                }
+            }
+        }
+
+        for name in TARGET_CFG_NAMES.iter() {
+            if !self.target.cfg.contains(&Cfg::Name(name.to_string())) {
+                defines
+                    .entry(name.to_string())
+                    .or_insert_with(|| cfg_macro(prefix, name).into());
+            }
+        }
+    }
+
+    /// Expand the crate as the library is built, with the same features and
+    /// target and with `--cfg cargo_c` for the crate only, returning the path
+    /// of the expanded source
+    fn expand(&self, ws: &Workspace, crate_path: &Path) -> anyhow::Result<PathBuf> {
+        let target_dir = ws.target_dir().as_path_unlocked().join("capi-expand");
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let features = self
+            .enabled_features
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        ws.config().shell().status("Expanding", &self.package)?;
+
+        let mut cmd = std::process::Command::new(cargo);
+        cmd.arg("rustc")
+            .arg("--lib")
+            .arg("--manifest-path")
+            .arg(crate_path.join("Cargo.toml"))
+            .arg("--no-default-features")
+            .arg("--features")
+            .arg(features)
+            .env("CARGO_TARGET_DIR", &target_dir);
+        if self.release {
+            cmd.arg("--release");
+        }
+        if self.target.verbatim.is_some() {
+            cmd.arg("--target").arg(&self.target.triple);
+        }
+        if let Some(version_rs) = self.version_rs.as_ref() {
+            cmd.env("CARGO_C_VERSION_RS", version_rs);
+        }
+        cmd.arg("--")
+            .arg("-Zunpretty=expanded")
+            .arg("--cfg")
+            .arg("cargo_c");
+
+        let out = error::run(&mut cmd)?;
+
+        let path = target_dir.join(format!("{}-expanded.rs", self.package));
+        std::fs::create_dir_all(&target_dir)?;
+        std::fs::write(&path, &out.stdout)?;
+
+        Ok(path)
+    }
+}
+
+/// Whether cbindgen is configured to expand the crate. cargo-c expands it
+/// itself, so only the crate itself can be listed in `parse.expand.crates`.
+fn expands(config: &toml::value::Table, package: &str) -> anyhow::Result<bool> {
+    let crates = config
+        .get("parse")
+        .and_then(|parse| parse.get("expand"))
+        .and_then(|expand| expand.get("crates"))
+        .and_then(|crates| crates.as_array());
+
+    let crates = match crates {
+        Some(crates) if !crates.is_empty() => crates,
+        _ => return Ok(false),
+    };
+
+    for krate in crates {
+        if krate.as_str().map(|k| k.replace('-', "_")) != Some(package.replace('-', "_")) {
+            anyhow::bail!(
+                "Only the crate itself can be expanded, `parse.expand.crates` lists {}",
+                krate
+            );
+        }
+    }
+
+    Ok(true)
+}
+
+/// Get the table at `key`, replacing any other value
+fn table_mut<'a>(table: &'a mut toml::value::Table, key: &str) -> &'a mut toml::value::Table {
+    let value = table
+        .entry(key)
+        .or_insert_with(|| toml::Value::Table(Default::default()));
+    if !value.is_table() {
+        *value = toml::Value::Table(Default::default());
+    }
+    match value {
+        toml::Value::Table(table) => table,
+        _ => unreachable!(),
+    }
+}
+
+/// cbindgen configuration: `cbindgen.toml` with the `header.cbindgen` table
+/// merged over it. Without `cbindgen.toml` a C header guarded by
+/// `<NAME>_H`, derived from the header name, is produced by default.
+fn cbindgen_config(
+    header: &HeaderCApiConfig,
+    root_path: &Path,
+) -> anyhow::Result<toml::value::Table> {
+    let path = root_path.join("cbindgen.toml");
+
+    let mut config = if path.exists() {
+        let s = std::fs::read_to_string(&path)?;
+        toml::from_str(&s).with_context(|| format!("Cannot parse {}", path.display()))?
+    } else {
+        let mut table = toml::value::Table::new();
+        table.insert("language".into(), "C".into());
+        table.insert(
+            "include_guard".into(),
+            format!("{}_H", macro_prefix(&header.name)).into(),
+        );
+        toml::Value::Table(table)
+    };
+
+    if let Some(embedded) = header.cbindgen.clone() {
+        metadata::merge(&mut config, embedded);
+    }
+
+    match config {
+        toml::Value::Table(table) => Ok(table),
+        _ => anyhow::bail!("Invalid cbindgen configuration"),
+    }
+}
+
+/// Build the C headers at the `include` paths computed by `BuildTargets`
+fn build_include_file(
+    ws: &Workspace,
+    header: &HeaderCApiConfig,
+    cfg: &HeaderCfg,
+    include: &[PathBuf],
     root_path: &PathBuf,
+    lib_dir: &Path,
+) -> anyhow::Result<()> {
+    let crate_path = root_path;
+
+    let generator = match header.generator {
+        HeaderGenerator::Builtin(generator) => generator,
+        HeaderGenerator::Command {
+            ref command,
+            ref output,
+        } => {
+            if !header.files.is_empty() {
+                anyhow::bail!("A custom header generator cannot split the header in `files`");
+            }
+            return build_include_file_with_command(
+                ws,
+                command,
+                output.as_deref(),
+                &include[0],
+                crate_path,
+            );
+        }
+    };
+
+    let expanded = if expands(&cbindgen_config(header, crate_path)?, &cfg.package)? {
+        Some(cfg.expand(ws, crate_path)?)
+    } else {
+        None
+    };
+
+    if header.files.is_empty() {
+        return build_include_file_with_cbindgen(
+            ws,
+            header,
+            None,
+            cfg,
+            generator,
+            &include[0],
+            crate_path,
+            expanded.as_deref(),
+            crate_path,
+        );
+    }
+
+    // The umbrella header, if any, comes first
+    let files = &include[include.len() - header.files.len()..];
+
+    for (file, path) in header.files.iter().zip(files) {
+        let src = match file.module {
+            Some(ref module) => module_path(lib_dir, module)?,
+            None => crate_path.to_path_buf(),
+        };
+        build_include_file_with_cbindgen(
+            ws,
+            header,
+            Some(file),
+            cfg,
+            generator,
+            path,
+            &src,
+            expanded.as_deref(),
+            crate_path,
+        )?;
+    }
+
+    if header.umbrella {
+        build_umbrella_header(ws, header, &include[0], files)?;
+    }
+
+    Ok(())
+}
+
+/// Source file of `module`, e.g. `codec::h264`, in the lib directory
+fn module_path(lib_dir: &Path, module: &str) -> anyhow::Result<PathBuf> {
+    let mut path = lib_dir.to_path_buf();
+    path.extend(module.split("::"));
+
+    let candidates = [path.with_extension("rs"), path.join("mod.rs")];
+    candidates
+        .iter()
+        .find(|p| p.is_file())
+        .cloned()
+        .with_context(|| format!("Cannot find the source of the module `{}`", module))
+}
+
+/// Run a custom generator, it writes the header to `$CARGO_C_HEADER_OUTPUT`
+/// or to `output`
+fn build_include_file_with_command(
+    ws: &Workspace,
+    command: &[String],
+    output: Option<&Path>,
+    include_path: &Path,
+    crate_path: &Path,
 ) -> anyhow::Result<()> {
+    let (program, args) = command
+        .split_first()
+        .context("The header generator command is empty")?;
+
     ws.config()
         .shell()
-        .status("Building", "header file using cbindgen")?;
-    let mut header_name = PathBuf::from(name);
-    header_name.set_extension("h");
-    let include_path = root_output.join(header_name);
-    let crate_path = root_path;
+        .status("Building", format!("header file using `{}`", program))?;
 
-    // TODO: map the errors
-    let mut config = cbindgen::Config::from_root_or_default(crate_path);
-    let warning = config.autogen_warning.unwrap_or_default();
-    let version_info = format!(
-        "\n#define {0}_MAJOR {1}\n#define {0}_MINOR {2}\n#define {0}_PATCH {3}\n",
-        name.to_uppercase(),
-        version.major,
-        version.minor,
-        version.patch
-    );
-    config.autogen_warning = Some(warning + &version_info);
-    cbindgen::Builder::new()
-        .with_crate(crate_path)
-        .with_config(config)
-        .generate()
-        .unwrap()
-        .write_to_file(include_path);
+    // Do not mistake the header of a previous build for the new one
+    if include_path.exists() {
+        std::fs::remove_file(include_path)?;
+    }
+
+    let mut cmd = std::process::Command::new(program);
+    cmd.args(args)
+        .current_dir(crate_path)
+        .env("CARGO_C_HEADER_OUTPUT", include_path);
+    error::run(&mut cmd)?;
+
+    match output {
+        Some(output) => {
+            std::fs::copy(crate_path.join(output), include_path).with_context(|| {
+                format!("Cannot copy the generated header {}", output.display())
+            })?;
+        }
+        None if !include_path.exists() => {
+            return Err(Error::HeaderGeneration {
+                message: format!(
+                    "$CARGO_C_HEADER_OUTPUT ({}) was not written",
+                    include_path.display()
+                ),
+                location: format!("`{}`", command.join(" ")),
+            }
+            .into());
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Build a header using the cbindgen library or the cbindgen binary, `src`
+/// is either the crate directory or the source of a module when the header
+/// is split in `files`. The crate directory is replaced by the `expanded`
+/// source when the crate is expanded.
+#[allow(clippy::too_many_arguments)]
+fn build_include_file_with_cbindgen(
+    ws: &Workspace,
+    header: &HeaderCApiConfig,
+    file: Option<&HeaderFileCApiConfig>,
+    cfg: &HeaderCfg,
+    generator: BuiltinGenerator,
+    include_path: &Path,
+    src: &Path,
+    expanded: Option<&Path>,
+    crate_path: &Path,
+) -> anyhow::Result<()> {
+    let mut config = cbindgen_config(header, crate_path)?;
+
+    let mut macros = version_macros(&header.version);
+    if header.feature_macros {
+        macros += &feature_macros(&header.version.prefix, &cfg.enabled_features);
+    }
+    let after_includes = config
+        .get("after_includes")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let after_includes = macros + after_includes;
+    config.insert("after_includes".into(), after_includes.into());
+
+    if expanded.is_some() {
+        table_mut(&mut config, "parse").remove("expand");
+    }
+
+    let src = match expanded {
+        Some(expanded) if src == crate_path => expanded,
+        _ => {
+            cfg.apply_defines(&mut config, &header.version.prefix, header.feature_macros);
+            src
+        }
+    };
+
+    let what = match file {
+        Some(file) => {
+            let guard = format!(
+                "{}_{}_H",
+                macro_prefix(&header.name),
+                c_identifier(&file.name)
+            );
+            config.insert("include_guard".into(), guard.into());
+
+            let export = table_mut(&mut config, "export");
+            for (key, items) in [("include", &file.include), ("exclude", &file.exclude)].iter() {
+                let list = export
+                    .entry(*key)
+                    .or_insert_with(|| toml::Value::Array(Vec::new()));
+                if let toml::Value::Array(list) = list {
+                    list.extend(items.iter().cloned().map(toml::Value::from));
+                }
+            }
+
+            format!("header file {}.h", file.name)
+        }
+        None => "header file".to_string(),
+    };
+
+    if let Some(dir) = include_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    match generator {
+        BuiltinGenerator::Bundled => {
+            ws.config()
+                .shell()
+                .status("Building", format!("{} using cbindgen", what))?;
+
+            let config: cbindgen::Config = toml::Value::Table(config)
+                .try_into()
+                .context("Invalid cbindgen configuration")?;
+
+            let builder = if src == crate_path {
+                cbindgen::Builder::new().with_crate(crate_path)
+            } else {
+                cbindgen::Builder::new().with_src(src)
+            };
+
+            builder
+                .with_config(config)
+                .generate()
+                .map_err(|err| Error::header_generation(err, &crate_path.join("Cargo.toml")))?
+                .write_to_file(include_path);
+        }
+        BuiltinGenerator::CbindgenCli => {
+            let cbindgen = std::env::var_os("CBINDGEN").unwrap_or_else(|| "cbindgen".into());
+
+            ws.config()
+                .shell()
+                .status("Building", format!("{} using the cbindgen binary", what))?;
+
+            let config_path = include_path.with_extension("cbindgen.toml");
+            let config = toml::to_string(&toml::Value::Table(config))?;
+            std::fs::write(&config_path, config)?;
+
+            // cbindgen parses a crate directory or a single source file
+            let mut cmd = std::process::Command::new(cbindgen);
+            cmd.arg("--config")
+                .arg(&config_path)
+                .arg("--output")
+                .arg(include_path)
+                .arg(src);
+            error::run(&mut cmd)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Header including all the `files`
+fn build_umbrella_header(
+    ws: &Workspace,
+    header: &HeaderCApiConfig,
+    path: &Path,
+    files: &[PathBuf],
+) -> anyhow::Result<()> {
+    ws.config()
+        .shell()
+        .status("Building", "umbrella header file")?;
+
+    let guard = format!("{}_H", macro_prefix(&header.name));
+    let mut content = format!("#ifndef {0}\n#define {0}\n\n", guard);
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        content += &format!("#include \"{}\"\n", name);
+    }
+    content += &format!("\n#endif /* {} */\n", guard);
+
+    std::fs::write(path, content)?;
 
     Ok(())
 }
 
-/// Copy the pre-built C header from the asset directory
+/// Files matching the `header.extra` glob patterns, relative to the crate root
+fn extra_headers(root_path: &Path, patterns: &[String]) -> anyhow::Result<Vec<PathBuf>> {
+    let root = glob::Pattern::escape(&root_path.to_string_lossy());
+    let mut headers = Vec::new();
+
+    for pattern in patterns {
+        let paths = glob::glob(&format!("{}/{}", root, pattern))
+            .with_context(|| format!("Invalid header.extra pattern `{}`", pattern))?;
+
+        let matched = headers.len();
+        for path in paths {
+            let path = path?;
+            if path.is_file() {
+                headers.push(path);
+            }
+        }
+
+        if headers.len() == matched {
+            anyhow::bail!(
+                "The header.extra pattern `{}` does not match any file",
+                pattern
+            );
+        }
+    }
+
+    Ok(headers)
+}
+
+/// Copy the pre-built C headers from the asset directory
+///
+/// A `<header>.h.in` template takes precedence over `<header>.h`, its
+/// `@VARIABLE@` placeholders are substituted.
 fn copy_prebuilt_include_file(
     ws: &Workspace,
     name: &str,
-    root_output: &PathBuf,
+    header: &HeaderCApiConfig,
+    include: &[PathBuf],
     root_path: &PathBuf,
 ) -> anyhow::Result<()> {
     ws.config()
         .shell()
         .status("Building", "pre-built header file")?;
-    let mut header_name = PathBuf::from(name);
-    header_name.set_extension("h");
 
-    let source_path = root_path.join("assets").join(&header_name);
-    let target_path = root_output.join(header_name);
+    for target_path in include {
+        let header_name = target_path.file_name().unwrap_or_default();
+        let source_path = root_path.join("assets").join(header_name);
+        let template_path = source_path.with_extension("h.in");
 
-    std::fs::copy(source_path, target_path)?;
+        if let Some(dir) = target_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        if template_path.exists() {
+            let template = std::fs::read_to_string(&template_path)
+                .with_context(|| format!("Cannot read {}", template_path.display()))?;
+            let (content, unknown) = substitute(&template, &template_variables(name, header));
+            for var in unknown {
+                ws.config().shell().warn(format!(
+                    "{}: unknown variable @{}@",
+                    template_path.display(),
+                    var
+                ))?;
+            }
+            std::fs::write(target_path, content)?;
+        } else {
+            std::fs::copy(&source_path, target_path)
+                .with_context(|| format!("Cannot copy {}", source_path.display()))?;
+        }
+    }
 
     Ok(())
 }
 
+/// Variables available to the header templates, the user-defined ones
+/// override the builtin ones
+fn template_variables(name: &str, header: &HeaderCApiConfig) -> BTreeMap<String, String> {
+    let version = &header.version.version;
+
+    let mut vars = BTreeMap::new();
+    vars.insert("NAME".to_string(), name.to_string());
+    vars.insert("PREFIX".to_string(), header.version.prefix.clone());
+    vars.insert(
+        "VERSION".to_string(),
+        format!("{}.{}.{}", version.major, version.minor, version.patch),
+    );
+    vars.insert("VERSION_MAJOR".to_string(), version.major.to_string());
+    vars.insert("VERSION_MINOR".to_string(), version.minor.to_string());
+    vars.insert("VERSION_PATCH".to_string(), version.patch.to_string());
+    vars.extend(header.variables.clone());
+
+    vars
+}
+
+/// Replace the `@VARIABLE@` placeholders, the unknown ones are left as they
+/// are and returned
+fn substitute(template: &str, vars: &BTreeMap<String, String>) -> (String, Vec<String>) {
+    let re = regex::Regex::new(r"@([A-Za-z_][A-Za-z0-9_]*)@").unwrap();
+    let mut unknown = Vec::new();
+
+    let content = re.replace_all(template, |caps: &regex::Captures| {
+        match vars.get(&caps[1]) {
+            Some(value) => value.clone(),
+            None => {
+                unknown.push(caps[1].to_string());
+                caps[0].to_string()
+            }
+        }
+    });
+
+    (content.into_owned(), unknown)
+}
+
+/// Generate the headers of `cpkg` at the `include` paths, or copy the
+/// pre-built ones
+pub(crate) fn build_headers(
+    ws: &Workspace,
+    pkg: &Package,
+    cpkg: &CPackage,
+    compile_opts: &ops::CompileOptions,
+    rustc_target: &target::Target,
+    include: &[PathBuf],
+    version_rs: Option<&Path>,
+) -> anyhow::Result<()> {
+    let header = &cpkg.capi_config.header;
+    let root_path = pkg.root().to_path_buf();
+
+    if header.generation {
+        let cfg = HeaderCfg::new(ws, pkg, compile_opts, rustc_target, version_rs)?;
+        let lib_dir = lib_dir(pkg).unwrap_or_else(|| root_path.join("src"));
+        build_include_file(ws, header, &cfg, include, &root_path, &lib_dir)
+    } else {
+        copy_prebuilt_include_file(ws, &cpkg.name, header, include, &root_path)
+    }
+}
+
+/// Write the pkg-config file to install, its static variant and the one
+/// pointing at the build directory
 fn build_pc_file(
     ws: &Workspace,
-    name: &str,
-    root_output: &PathBuf,
+    build_targets: &BuildTargets,
+    root_output: &Path,
+    lib_name: &str,
     pc: &PkgConfig,
 ) -> anyhow::Result<()> {
-    ws.config().shell().status("Building", "pkg-config file")?;
-    let pc_path = root_output.join(&format!("{}.pc", name));
+    ws.config().shell().status("Building", "pkg-config files")?;
 
-    let mut out = std::fs::File::create(pc_path)?;
+    let problems = pc.validate();
+    if !problems.is_empty() {
+        return Err(Error::InvalidPkgConfig {
+            path: build_targets.pc.clone(),
+            problems,
+        }
+        .into());
+    }
 
-    let buf = pc.render();
+    std::fs::write(&build_targets.pc, pc.render())?;
 
-    out.write_all(buf.as_ref())?;
+    if let (Some(pc_static), Some(static_lib)) = (
+        build_targets.pc_static.as_ref(),
+        build_targets.static_lib.as_ref(),
+    ) {
+        let static_lib = static_lib.file_name().unwrap().to_string_lossy();
+        std::fs::write(pc_static, pc.to_static(lib_name, &static_lib).render())?;
+    }
+
+    let include_dirs: Vec<&Path> = build_targets
+        .include
+        .iter()
+        .chain(build_targets.extra_headers.iter())
+        .filter_map(|header| header.parent())
+        .fold(Vec::new(), |mut dirs, dir| {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+            dirs
+        });
+    let pc_uninstalled = pc.uninstalled(root_output, &include_dirs);
+    std::fs::write(&build_targets.pc_uninstalled, pc_uninstalled.render())?;
 
     Ok(())
 }
 
-fn patch_lib_kind_in_target(ws: &mut Workspace, libkinds: &[&str]) -> anyhow::Result<()> {
-    use cargo::core::LibKind::*;
+/// Copy all the artifacts to `out_dir`, regardless of the target and profile
+fn copy_to_out_dir(
+    ws: &Workspace,
+    build_targets: &BuildTargets,
+    root_output: &Path,
+    out_dir: &PathBuf,
+) -> anyhow::Result<()> {
+    ws.config()
+        .shell()
+        .status("Copying", format!("artifacts to {}", out_dir.display()))?;
 
-    let pkg = ws.current_mut()?;
-    let manifest = pkg.manifest_mut();
-    let targets = manifest.targets_mut();
+    std::fs::create_dir_all(out_dir)?;
+
+    // The uninstalled pkg-config file points at the build directory
+    for (_, path) in build_targets
+        .artifacts()
+        .into_iter()
+        .filter(|(kind, _)| *kind != "pc-uninstalled")
+    {
+        // Split headers keep their `include/<name>/` directory, since their
+        // names may clash across packages
+        let dest = match path.strip_prefix(root_output) {
+            Ok(rel) if rel.starts_with("include") => out_dir.join(rel),
+            _ => out_dir.join(path.file_name().unwrap()),
+        };
+        if let Some(dir) = dest.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::copy(path, &dest)
+            .with_context(|| format!("Cannot copy {} to {}.", path.display(), dest.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Machine-readable message describing an artifact, emitted with
+/// `--message-format json`
+#[derive(Serialize)]
+struct ArtifactMessage<'a> {
+    reason: &'static str,
+    package_id: PackageId,
+    kind: &'a str,
+    path: &'a Path,
+}
+
+fn emit_artifact_messages(
+    ws: &Workspace,
+    package_id: PackageId,
+    build_targets: &BuildTargets,
+) -> anyhow::Result<()> {
+    for (kind, path) in build_targets.artifacts() {
+        let message = ArtifactMessage {
+            reason: "capi-artifact",
+            package_id,
+            kind,
+            path,
+        };
+        let json = serde_json::to_string(&message)?;
+        writeln!(ws.config().shell().out(), "{}", json)?;
+    }
+
+    Ok(())
+}
+
+fn patch_lib_kind_in_target(
+    ws: &mut Workspace,
+    pkg_id: PackageId,
+    libkinds: &[&str],
+) -> anyhow::Result<()> {
+    use cargo::core::LibKind::*;
 
     let kinds: Vec<_> = libkinds.iter().map(|&kind| Other(kind.into())).collect();
 
-    for target in targets.iter_mut() {
-        if target.is_lib() {
-            *target.kind_mut() = TargetKind::Lib(kinds.clone());
+    if let Some(pkg) = ws.members_mut().find(|p| p.package_id() == pkg_id) {
+        let manifest = pkg.manifest_mut();
+        let targets = manifest.targets_mut();
+
+        for target in targets.iter_mut() {
+            if target.is_lib() {
+                *target.kind_mut() = TargetKind::Lib(kinds.clone());
+            }
         }
     }
 
     Ok(())
 }
 
-fn patch_capi_feature(
+/// Library types requested explicitly or configured in the manifest
+fn library_types(build: &CApiBuild, capi_config: &CApiConfig) -> Vec<&'static str> {
+    build
+        .library_types
+        .as_ref()
+        .unwrap_or(&capi_config.library.types)
+        .iter()
+        .map(|t| t.as_str())
+        .collect()
+}
+
+pub(crate) fn patch_capi_feature(
     compile_opts: &mut ops::CompileOptions,
-    ws: &Workspace,
+    pkg: &Package,
 ) -> anyhow::Result<()> {
-    let pkg = ws.current()?;
     let manifest = pkg.manifest();
 
     if manifest.summary().features().get("capi").is_some() {
@@ -146,43 +1068,34 @@ fn build_def_file(
             .arg(targetdir.join(format!("{}.dll", name)));
         dumpbin.arg(format!("/OUT:{}", txt_path.to_str().unwrap()));
 
-        let out = dumpbin.output()?;
-        if out.status.success() {
-            let txt_file = File::open(txt_path)?;
-            let buf_reader = BufReader::new(txt_file);
-            let mut def_file = File::create(targetdir.join(format!("{}.def", name)))?;
-            writeln!(def_file, "{}", "EXPORTS".to_string())?;
-
-            // The Rust loop below is analogue to the following loop.
-            // for /f "skip=19 tokens=4" %A in (file.txt) do echo %A > file.def
-            // The most recent versions of dumpbin adds three lines of copyright
-            // information before the relevant content.
-            // If the "/OUT:file.txt" dumpbin's option is used, the three
-            // copyright lines are added to the shell, so the txt file
-            // contains three lines less.
-            // The Rust loop first skips 16 lines and then, for each line,
-            // deletes all the characters up to the fourth space included
-            // (skip=16 tokens=4)
-            for line in buf_reader
-                .lines()
-                .skip(16)
-                .take_while(|l| !l.as_ref().unwrap().is_empty())
-                .map(|l| {
-                    l.unwrap()
-                        .as_str()
-                        .split_whitespace()
-                        .nth(3)
-                        .unwrap()
-                        .to_string()
-                })
-            {
-                writeln!(def_file, "\t{}", line)?;
+        error::run(&mut dumpbin)?;
+
+        let txt_file = File::open(txt_path)?;
+        let buf_reader = BufReader::new(txt_file);
+        let mut def_file = File::create(targetdir.join(format!("{}.def", name)))?;
+        writeln!(def_file, "{}", "EXPORTS".to_string())?;
+
+        // The Rust loop below is analogue to the following loop.
+        // for /f "skip=19 tokens=4" %A in (file.txt) do echo %A > file.def
+        // The most recent versions of dumpbin adds three lines of copyright
+        // information before the relevant content.
+        // If the "/OUT:file.txt" dumpbin's option is used, the three
+        // copyright lines are added to the shell, so the txt file
+        // contains three lines less.
+        // The Rust loop first skips 16 lines and then, for each line,
+        // deletes all the characters up to the fourth space included
+        // (skip=16 tokens=4)
+        for line in buf_reader.lines().skip(16) {
+            let line = line?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(symbol) = line.split_whitespace().nth(3) {
+                writeln!(def_file, "\t{}", symbol)?;
             }
-
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Command failed {:?}", dumpbin))
         }
+
+        Ok(())
     } else {
         Ok(())
     }
@@ -209,7 +1122,7 @@ fn build_implib_file(
         let binutils_arch = match arch.as_str() {
             "x86_64" => "i386:x86-64",
             "x86" => "i386",
-            _ => unimplemented!("Windows support for {} is not implemented yet.", arch),
+            _ => return Err(Error::UnsupportedTarget(format!("{}-windows-gnu", arch)).into()),
         };
 
         let mut dlltool_command =
@@ -223,12 +1136,9 @@ fn build_implib_file(
             .arg("-d")
             .arg(targetdir.join(format!("{}.def", name)));
 
-        let out = dlltool_command.output()?;
-        if out.status.success() {
-            Ok(())
-        } else {
-            Err(anyhow::anyhow!("Command failed {:?}", dlltool_command))
-        }
+        error::run(&mut dlltool_command)?;
+
+        Ok(())
     } else {
         Ok(())
     }
@@ -241,7 +1151,8 @@ fn fingerprint(build_targets: &BuildTargets) -> anyhow::Result<Option<u64>> {
 
     let mut hasher = DefaultHasher::new();
 
-    let mut paths = vec![&build_targets.include];
+    let mut paths: Vec<_> = build_targets.include.iter().collect();
+    paths.extend(&build_targets.extra_headers);
     paths.extend(&build_targets.static_lib);
     paths.extend(&build_targets.shared_lib);
 
@@ -259,52 +1170,163 @@ fn fingerprint(build_targets: &BuildTargets) -> anyhow::Result<Option<u64>> {
     Ok(Some(hasher.finish()))
 }
 
+#[derive(Debug, Serialize)]
 pub struct CApiConfig {
     pub header: HeaderCApiConfig,
     pub pkg_config: PkgConfigCApiConfig,
     pub library: LibraryCApiConfig,
 }
 
+#[derive(Debug, Serialize)]
 pub struct HeaderCApiConfig {
     pub name: String,
     pub subdirectory: bool,
     pub generation: bool,
+    pub version: HeaderVersionCApiConfig,
+    pub feature_macros: bool,
+    pub cbindgen: Option<toml::Value>,
+    pub generator: HeaderGenerator,
+    /// Headers the C-API is split in, empty if there is a single one
+    pub files: Vec<HeaderFileCApiConfig>,
+    pub umbrella: bool,
+    /// Glob patterns of the hand-written headers to install
+    pub extra: Vec<String>,
+    /// Variables substituted in the pre-built header templates
+    pub variables: BTreeMap<String, String>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct HeaderFileCApiConfig {
+    pub name: String,
+    pub module: Option<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeaderVersionCApiConfig {
+    pub prefix: String,
+    pub version: Version,
+    pub string: bool,
+    pub packed: bool,
+    pub check: bool,
+    pub functions: bool,
+}
+
+#[derive(Debug, Serialize)]
 pub struct PkgConfigCApiConfig {
     pub name: String,
     pub description: String,
     pub version: String,
+    pub requires: Vec<String>,
+    pub requires_private: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub url: Option<String>,
+    pub libs: Vec<String>,
+    pub libs_private: Vec<String>,
+    pub cflags: Vec<String>,
+    pub relocatable: bool,
+    pub static_cflags: Vec<String>,
+    pub static_pc: bool,
+    pub variables: BTreeMap<String, String>,
 }
 
+#[derive(Debug, Serialize)]
 pub struct LibraryCApiConfig {
     pub name: String,
     pub version: Version,
+    pub types: Vec<LibraryType>,
 }
 
-fn load_manifest_capi_config(
-    name: &str,
-    root_path: &PathBuf,
-    ws: &Workspace,
-) -> anyhow::Result<CApiConfig> {
+/// Read the `<section>.metadata.capi` table from the manifest at `path`
+fn read_manifest_capi_table(path: &Path, section: &str) -> anyhow::Result<Option<toml::Value>> {
     use std::io::Read;
-    let mut manifest = std::fs::File::open(root_path.join("Cargo.toml"))?;
+    let mut manifest = std::fs::File::open(path)?;
     let mut manifest_str = String::new();
     manifest.read_to_string(&mut manifest_str)?;
 
     let toml = manifest_str.parse::<toml::Value>()?;
 
-    let capi = toml
-        .get("package")
+    Ok(toml
+        .get(section)
         .and_then(|v| v.get("metadata"))
-        .and_then(|v| v.get("capi"));
+        .and_then(|v| v.get("capi"))
+        .cloned())
+}
 
-    if let Some(min_version) = capi
-        .as_ref()
-        .and_then(|capi| capi.get("min_version"))
-        .and_then(|v| v.as_str())
-    {
-        let min_version = Version::parse(min_version)?;
+/// Load the C-API settings of `pkg`.
+///
+/// Along with the settings it returns the manifest section each key was
+/// taken from, keys missing from it use the default value.
+/// Read and validate the `workspace.metadata.capi` table, warning about
+/// unknown keys once for the whole workspace
+fn load_workspace_capi_config(ws: &Workspace) -> anyhow::Result<Option<toml::Value>> {
+    let ws_capi = read_manifest_capi_table(&ws.root().join("Cargo.toml"), "workspace")?;
+
+    if let Some(ws_capi) = ws_capi.as_ref() {
+        let mut warnings = Vec::new();
+        metadata::parse(metadata::WORKSPACE_CAPI_KEY, ws_capi.clone(), &mut warnings)?;
+        for warning in warnings {
+            ws.config().shell().warn(warning)?;
+        }
+    }
+
+    Ok(ws_capi)
+}
+
+fn load_manifest_capi_config(
+    name: &str,
+    pkg: &Package,
+    ws: &Workspace,
+    ws_capi: Option<&toml::Value>,
+    target: &target::Target,
+) -> anyhow::Result<(CApiConfig, BTreeMap<String, String>)> {
+    let mut warnings = Vec::new();
+    let mut origins = BTreeMap::new();
+    let mut capi = toml::Value::Table(Default::default());
+
+    // The workspace settings act as defaults, the package ones override them.
+    if let Some(ws_capi) = ws_capi {
+        metadata::record_origins(ws_capi, metadata::WORKSPACE_CAPI_KEY, &mut origins);
+        capi = ws_capi.clone();
+    }
+
+    if let Some(pkg_capi) = read_manifest_capi_table(pkg.manifest_path(), "package")? {
+        metadata::parse(metadata::CAPI_KEY, pkg_capi.clone(), &mut warnings)?;
+        metadata::record_origins(&pkg_capi, metadata::CAPI_KEY, &mut origins);
+        metadata::merge(&mut capi, pkg_capi);
+    }
+
+    for warning in warnings {
+        ws.config().shell().warn(warning)?;
+    }
+
+    // The overrides may come from the workspace or the package section
+    let (target_origins, mut origins): (BTreeMap<_, _>, BTreeMap<_, _>) = origins
+        .into_iter()
+        .partition(|(key, _)| key.starts_with("target."));
+
+    let applied = metadata::apply_target_overrides(
+        metadata::CAPI_KEY,
+        &mut capi,
+        &target.triple,
+        &target.cfg,
+    )?;
+
+    for (platform, overrides) in applied.iter() {
+        let mut keys = BTreeMap::new();
+        metadata::record_origins(overrides, "", &mut keys);
+        for (key, _) in keys {
+            let section = target_origins
+                .get(&format!("target.{}.{}", platform, key))
+                .map_or(metadata::CAPI_KEY, String::as_str);
+            origins.insert(key, format!("{}.target.{}", section, platform));
+        }
+    }
+
+    let capi = metadata::parse(metadata::CAPI_KEY, capi, &mut Vec::new())?;
+
+    if let Some(min_version) = capi.min_version {
         let version = Version::parse(env!("CARGO_PKG_VERSION"))?;
         if min_version > version {
             anyhow::bail!(
@@ -315,153 +1337,231 @@ fn load_manifest_capi_config(
         }
     }
 
-    let header = capi.and_then(|v| v.get("header"));
-
-    let header = if let Some(ref capi) = capi {
-        HeaderCApiConfig {
-            name: header
-                .as_ref()
-                .and_then(|h| h.get("name"))
-                .or_else(|| capi.get("header_name"))
-                .map(|v| v.clone().try_into())
-                .unwrap_or(Ok(String::from(name)))?,
-            subdirectory: header
-                .as_ref()
-                .and_then(|h| h.get("subdirectory"))
-                .map(|v| v.clone().try_into())
-                .unwrap_or(Ok(true))?,
-            generation: header
-                .as_ref()
-                .and_then(|h| h.get("generation"))
-                .map(|v| v.clone().try_into())
-                .unwrap_or(Ok(true))?,
-        }
-    } else {
-        HeaderCApiConfig {
-            name: String::from(name),
-            subdirectory: true,
-            generation: true,
-        }
+    let library_version = capi
+        .library
+        .version
+        .unwrap_or_else(|| pkg.version().clone());
+
+    let header_name = capi
+        .header
+        .name
+        .or(capi.header_name)
+        .unwrap_or_else(|| String::from(name));
+
+    let header_version = capi.header.version;
+    let version = HeaderVersionCApiConfig {
+        prefix: header_version
+            .prefix
+            .unwrap_or_else(|| macro_prefix(&header_name)),
+        version: match header_version.source {
+            Some(VersionSource::Library) => library_version.clone(),
+            _ => pkg.version().clone(),
+        },
+        string: header_version.string.unwrap_or(false),
+        packed: header_version.packed.unwrap_or(false),
+        check: header_version.check.unwrap_or(false),
+        functions: header_version.functions.unwrap_or(false),
     };
 
-    let pc = capi.and_then(|v| v.get("pkg_config"));
-    let pkg = ws.current().unwrap();
-    let mut pc_name = String::from(name);
-    let mut description = String::from(
-        pkg.manifest()
-            .metadata()
-            .description
-            .as_deref()
-            .unwrap_or_else(|| ""),
-    );
-    let mut version = pkg.version().to_string();
-
-    if let Some(ref pc) = pc {
-        if let Some(override_name) = pc.get("name").and_then(|v| v.as_str()) {
-            pc_name = String::from(override_name);
-        }
-        if let Some(override_description) = pc.get("description").and_then(|v| v.as_str()) {
-            description = String::from(override_description);
-        }
-        if let Some(override_version) = pc.get("version").and_then(|v| v.as_str()) {
-            version = String::from(override_version);
-        }
-    }
-
-    let pkg_config = PkgConfigCApiConfig {
-        name: pc_name,
-        description,
+    let header = HeaderCApiConfig {
+        name: header_name,
+        subdirectory: capi.header.subdirectory.unwrap_or(true),
+        generation: capi.header.generation.unwrap_or(true),
         version,
+        feature_macros: capi.header.feature_macros.unwrap_or(false),
+        cbindgen: capi.header.cbindgen,
+        generator: capi
+            .header
+            .generator
+            .unwrap_or(HeaderGenerator::Builtin(BuiltinGenerator::Bundled)),
+        files: capi
+            .header
+            .files
+            .into_iter()
+            .map(|file| HeaderFileCApiConfig {
+                name: file.name.trim_end_matches(".h").to_string(),
+                module: file.module,
+                include: file.include,
+                exclude: file.exclude,
+            })
+            .collect(),
+        umbrella: capi.header.umbrella.unwrap_or(false),
+        extra: capi.header.extra,
+        variables: capi.header.variables,
     };
 
-    let library = capi.and_then(|v| v.get("library"));
-    let mut lib_name = String::from(name);
-    let mut version = pkg.version().clone();
+    let pkg_metadata = pkg.manifest().metadata();
+    let description = pkg_metadata.description.as_deref().unwrap_or_else(|| "");
 
-    if let Some(ref library) = library {
-        if let Some(override_name) = library.get("name").and_then(|v| v.as_str()) {
-            lib_name = String::from(override_name);
-        }
-        if let Some(override_version) = library.get("version").and_then(|v| v.as_str()) {
-            version = Version::parse(override_version)?;
+    for var in ["prefix", "exec_prefix", "libdir", "includedir"].iter() {
+        if capi.pkg_config.variables.contains_key(*var) {
+            anyhow::bail!(
+                "`{}` is always defined in the pkg-config file and cannot be set in `pkg_config.variables`",
+                var
+            );
         }
     }
 
+    let pkg_config = PkgConfigCApiConfig {
+        name: capi.pkg_config.name.unwrap_or_else(|| String::from(name)),
+        description: capi
+            .pkg_config
+            .description
+            .unwrap_or_else(|| String::from(description)),
+        version: capi
+            .pkg_config
+            .version
+            .unwrap_or_else(|| pkg.version().to_string()),
+        requires: capi.pkg_config.requires,
+        requires_private: capi.pkg_config.requires_private,
+        conflicts: capi.pkg_config.conflicts,
+        url: capi
+            .pkg_config
+            .url
+            .or_else(|| pkg_metadata.homepage.clone())
+            .or_else(|| pkg_metadata.repository.clone()),
+        libs: capi.pkg_config.libs,
+        libs_private: capi.pkg_config.libs_private,
+        cflags: capi.pkg_config.cflags,
+        relocatable: capi.pkg_config.relocatable.unwrap_or(false),
+        static_cflags: capi.pkg_config.static_cflags,
+        static_pc: capi.pkg_config.static_pc.unwrap_or(false),
+        variables: capi.pkg_config.variables,
+    };
+
     let library = LibraryCApiConfig {
-        name: lib_name,
-        version,
+        name: capi.library.name.unwrap_or_else(|| String::from(name)),
+        version: library_version,
+        types: capi
+            .library
+            .types
+            .unwrap_or_else(|| vec![LibraryType::Staticlib, LibraryType::Cdylib]),
     };
 
-    Ok(CApiConfig {
+    let capi_config = CApiConfig {
         header,
         pkg_config,
         library,
-    })
+    };
+
+    Ok((capi_config, origins))
 }
 
-pub fn cbuild(
-    ws: &mut Workspace,
-    config: &Config,
-    args: &ArgMatches<'_>,
-) -> anyhow::Result<(BuildTargets, InstallPaths, CApiConfig)> {
-    let rustc_target = target::Target::new(args.target())?;
-    let libkinds = args
-        .values_of("library-type")
-        .map_or_else(|| vec!["staticlib", "cdylib"], |v| v.collect::<Vec<_>>());
-    let only_staticlib = !libkinds.contains(&"cdylib");
-
-    patch_lib_kind_in_target(ws, &libkinds)?;
-
-    let name = &ws
-        .current()?
-        .manifest()
+/// A C-API package built by `cbuild`
+#[derive(Debug, Serialize)]
+pub struct CPackage {
+    pub name: String,
+    pub capi_config: CApiConfig,
+    /// Manifest section each `capi_config` key was taken from
+    pub capi_config_origins: BTreeMap<String, String>,
+    pub build_targets: BuildTargets,
+    pub install_paths: InstallPaths,
+    #[serde(skip)]
+    package_id: PackageId,
+}
+
+fn has_capi_metadata(pkg: &Package) -> bool {
+    pkg.manifest()
+        .custom_metadata()
+        .and_then(|m| m.get("capi"))
+        .is_some()
+}
+
+fn lib_target_name(pkg: &Package) -> Option<String> {
+    pkg.manifest()
         .targets()
         .iter()
         .find(|t| t.is_lib())
-        .unwrap()
-        .crate_name();
-    let version = ws.current()?.version().clone();
-    let root_path = ws.current()?.root().to_path_buf();
-    let capi_config = load_manifest_capi_config(name, &root_path, &ws)?;
+        .map(|t| t.crate_name())
+}
 
-    let install_paths = InstallPaths::new(name, args, &capi_config);
+/// Directory holding the root source of the lib target
+fn lib_dir(pkg: &Package) -> Option<PathBuf> {
+    pkg.manifest()
+        .targets()
+        .iter()
+        .find(|t| t.is_lib())
+        .and_then(|t| t.src_path().path())
+        .and_then(Path::parent)
+        .map(Path::to_path_buf)
+}
 
-    let mut pc = PkgConfig::from_workspace(name, &install_paths, args, &capi_config);
+/// Resolve the packages selected by `--package`, `--workspace` and `--exclude`
+/// along with the crate name of their lib target.
+///
+/// When the whole workspace is selected only the members providing a
+/// `package.metadata.capi` section are considered, and the ones without a
+/// lib target are skipped with a warning.
+fn selected_packages(
+    ws: &Workspace,
+    spec: &ops::Packages,
+) -> anyhow::Result<Vec<(PackageId, String)>> {
+    let whole_workspace = matches!(spec, ops::Packages::All | ops::Packages::OptOut(_));
+
+    let candidates = || {
+        ws.members()
+            .filter(|pkg| has_capi_metadata(pkg) && lib_target_name(pkg).is_some())
+            .map(|pkg| pkg.name().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
 
-    let static_libs = get_static_libs_for_target(
-        rustc_target.verbatim.as_ref(),
-        &ws.target_dir().as_path_unlocked().to_path_buf(),
-    )?;
+    let packages = spec.get_packages(ws)?;
 
-    if only_staticlib {
-        pc.add_lib(&static_libs);
+    if let ops::Packages::Default = *spec {
+        if packages.len() > 1 {
+            return Err(Error::AmbiguousPackage {
+                candidates: candidates(),
+            }
+            .into());
+        }
     }
-    pc.add_lib_private(&static_libs);
 
-    let mut compile_opts = args.compile_options(
-        config,
-        CompileMode::Build,
-        Some(ws),
-        ProfileChecking::Checked,
-    )?;
+    let mut selected = Vec::new();
 
-    patch_capi_feature(&mut compile_opts, ws)?;
+    for pkg in packages {
+        if whole_workspace && !has_capi_metadata(pkg) {
+            continue;
+        }
 
-    compile_opts.filter = ops::CompileFilter::new(
-        ops::LibRule::True,
-        ops::FilterRule::none(),
-        ops::FilterRule::none(),
-        ops::FilterRule::none(),
-        ops::FilterRule::none(),
-    );
+        match lib_target_name(pkg) {
+            Some(name) => selected.push((pkg.package_id(), name)),
+            None if whole_workspace => ws.config().shell().warn(format!(
+                "skipping `{}`: it has a `package.metadata.capi` section but no lib target",
+                pkg.name()
+            ))?,
+            None => {
+                return Err(Error::MissingLibTarget {
+                    package: pkg.name().to_string(),
+                    candidates: candidates(),
+                }
+                .into())
+            }
+        }
+    }
 
-    compile_opts.export_dir = args.value_of_path("out-dir", config);
-    if compile_opts.export_dir.is_some() {
-        config
-            .cli_unstable()
-            .fail_if_stable_opt("--out-dir", 6790)?;
+    if selected.is_empty() {
+        match spec {
+            ops::Packages::Packages(names) if !names.is_empty() => anyhow::bail!(
+                "No package with a package.metadata.capi section found among `{}`",
+                names.join(", ")
+            ),
+            _ => anyhow::bail!(
+                "No package with a package.metadata.capi section found in the workspace"
+            ),
+        }
     }
 
+    Ok(selected)
+}
+
+/// Compute the output directory of the build without compiling anything
+pub(crate) fn root_output(
+    ws: &Workspace,
+    config: &Config,
+    target: Option<&str>,
+    compile_opts: &ops::CompileOptions,
+) -> anyhow::Result<PathBuf> {
     let profiles = Profiles::new(
         ws.profiles(),
         config,
@@ -475,60 +1575,177 @@ pub fn cbuild(
         .as_path_unlocked()
         .to_path_buf()
         .join(
-            args.target()
-                .map(|t| PathBuf::from(t))
+            target
+                .map(PathBuf::from)
                 .unwrap_or_else(|| PathBuf::from(".")),
         )
         .join(&profiles.get_dir_name());
 
-    let mut link_args: Vec<String> = rustc_target
-        .shared_object_link_args(&capi_config, &install_paths.libdir, &root_output)
-        .into_iter()
-        .flat_map(|l| vec!["-C".to_string(), format!("link-arg={}", l)])
-        .collect();
+    Ok(root_output)
+}
 
-    link_args.push("--cfg".into());
-    link_args.push("cargo_c".into());
+/// Resolve the settings, install paths and build targets of the selected
+/// packages and set up their lib targets.
+pub(crate) fn resolve_packages(
+    ws: &mut Workspace,
+    config: &Config,
+    build: &CApiBuild,
+    rustc_target: &target::Target,
+    root_output: &PathBuf,
+) -> anyhow::Result<Vec<CPackage>> {
+    let mut packages = Vec::new();
+
+    let ws_capi = load_workspace_capi_config(ws)?;
+
+    for (package_id, name) in selected_packages(ws, &build.package_spec()?)? {
+        let pkg = ws.members().find(|p| p.package_id() == package_id).unwrap();
+        let (capi_config, capi_config_origins) =
+            load_manifest_capi_config(&name, pkg, ws, ws_capi.as_ref(), rustc_target)?;
+
+        let extra_headers = extra_headers(pkg.root(), &capi_config.header.extra)?;
+
+        let libkinds = library_types(build, &capi_config);
+        patch_lib_kind_in_target(ws, package_id, &libkinds)?;
+
+        let install_paths = InstallPaths::new(&name, &build.install, &capi_config, config)?;
+        let mut build_targets =
+            BuildTargets::new(&name, rustc_target, root_output, &libkinds, &capi_config)?;
+        build_targets.extra_headers = extra_headers;
+
+        packages.push(CPackage {
+            name,
+            capi_config,
+            capi_config_origins,
+            build_targets,
+            install_paths,
+            package_id,
+        });
+    }
 
-    compile_opts.target_rustc_args = Some(link_args);
+    Ok(packages)
+}
 
-    let build_targets =
-        BuildTargets::new(&name, &rustc_target, &root_output, &libkinds, &capi_config);
+pub(crate) fn cbuild(
+    ws: &mut Workspace,
+    config: &Config,
+    build: &CApiBuild,
+    rustc_target: &target::Target,
+) -> anyhow::Result<Vec<CPackage>> {
+    let mut compile_opts = build.compile_options(config, ws)?;
 
-    let prev_hash = fingerprint(&build_targets)?;
+    let root_output = root_output(ws, config, build.target.as_deref(), &compile_opts)?;
 
-    let r = ops::compile(ws, &compile_opts)?;
-    assert_eq!(root_output, r.root_output);
+    let packages = resolve_packages(ws, config, build, rustc_target, &root_output)?;
 
-    let cur_hash = fingerprint(&build_targets)?;
+    let static_libs = get_static_libs_for_target(
+        rustc_target.verbatim.as_ref(),
+        &ws.target_dir().as_path_unlocked().to_path_buf(),
+    )?;
 
-    build_pc_file(&ws, &name, &root_output, &pc)?;
+    compile_opts.filter = ops::CompileFilter::new(
+        ops::LibRule::True,
+        ops::FilterRule::none(),
+        ops::FilterRule::none(),
+        ops::FilterRule::none(),
+        ops::FilterRule::none(),
+    );
 
-    if cur_hash.is_none() || prev_hash != cur_hash {
-        if !only_staticlib {
-            build_def_file(&ws, &name, &rustc_target, &root_output)?;
+    // dlltool argument overwrites environment var
+    let dlltool = build
+        .dlltool
+        .clone()
+        .or_else(|| std::env::var_os("DLLTOOL").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("dlltool"));
 
-            let mut dlltool = std::env::var_os("DLLTOOL")
-                .map(PathBuf::from)
-                .unwrap_or_else(|| PathBuf::from("dlltool"));
+    let features = compile_opts.features.clone();
 
-            // dlltool argument overwrites environment var
-            if args.value_of("dlltool").is_some() {
-                dlltool = args.value_of("dlltool").map(PathBuf::from).unwrap();
-            }
+    // Every package needs its own link arguments, so they are built one at a time.
+    for cpkg in packages.iter() {
+        let pkg = ws
+            .members()
+            .find(|p| p.package_id() == cpkg.package_id)
+            .unwrap();
+
+        let name = &cpkg.name;
+        let capi_config = &cpkg.capi_config;
+        let build_targets = &cpkg.build_targets;
+
+        let only_staticlib = build_targets.shared_lib.is_none();
 
-            build_implib_file(&ws, &name, &rustc_target, &root_output, &dlltool)?;
+        let mut pc = PkgConfig::from_workspace(name, &cpkg.install_paths, capi_config)?;
+
+        if only_staticlib {
+            pc.add_lib(&static_libs);
         }
+        pc.add_lib_private(&static_libs);
 
-        let header_name = &capi_config.header.name;
-        if capi_config.header.generation {
-            build_include_file(&ws, header_name, &version, &root_output, &root_path)?;
-        } else {
-            copy_prebuilt_include_file(&ws, header_name, &root_output, &root_path)?;
+        compile_opts.spec = ops::Packages::Packages(vec![pkg.name().to_string()]);
+        compile_opts.features = features.clone();
+        patch_capi_feature(&mut compile_opts, pkg)?;
+
+        let mut link_args: Vec<String> = rustc_target
+            .shared_object_link_args(capi_config, &cpkg.install_paths.libdir, &root_output)
+            .into_iter()
+            .flat_map(|l| vec!["-C".to_string(), format!("link-arg={}", l)])
+            .collect();
+
+        link_args.push("--cfg".into());
+        link_args.push("cargo_c".into());
+
+        compile_opts.target_rustc_args = Some(link_args);
+
+        let version_rs = build_version_functions_file(name, &capi_config.header, &root_output)?;
+        let exec: Arc<dyn Executor> = Arc::new(VersionFunctionsExecutor {
+            package_id: cpkg.package_id,
+            version_rs: version_rs.clone(),
+        });
+
+        let prev_hash = fingerprint(build_targets)?;
+
+        let r = ops::compile_with_exec(ws, &compile_opts, &exec)?;
+        assert_eq!(root_output, r.root_output);
+
+        let cur_hash = fingerprint(build_targets)?;
+
+        build_pc_file(
+            &ws,
+            build_targets,
+            &root_output,
+            &capi_config.library.name,
+            &pc,
+        )?;
+
+        let changed = cur_hash.is_none() || prev_hash != cur_hash;
+
+        if changed && !only_staticlib {
+            build_def_file(&ws, name, rustc_target, &root_output)?;
+            build_implib_file(&ws, name, rustc_target, &root_output, &dlltool)?;
+        }
+
+        // The fingerprint does not cover the pre-built headers and their
+        // template variables, so they are copied on every build
+        if changed || !capi_config.header.generation {
+            build_headers(
+                &ws,
+                pkg,
+                cpkg,
+                &compile_opts,
+                rustc_target,
+                &build_targets.include,
+                version_rs.as_deref(),
+            )?;
+        }
+
+        if let Some(out_dir) = build.out_dir.as_ref() {
+            copy_to_out_dir(ws, build_targets, &root_output, out_dir)?;
+        }
+
+        if compile_opts.build_config.emit_json() {
+            emit_artifact_messages(ws, cpkg.package_id, build_targets)?;
         }
     }
 
-    Ok((build_targets, install_paths, capi_config))
+    Ok(packages)
 }
 
 pub fn config_configure(config: &mut Config, args: &ArgMatches<'_>) -> CliResult {
@@ -553,3 +1770,230 @@ pub fn config_configure(config: &mut Config, args: &ArgMatches<'_>) -> CliResult
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn macro_prefix() {
+        assert_eq!(super::macro_prefix("foo"), "FOO");
+        assert_eq!(super::macro_prefix("my-lib.h"), "MY_LIB");
+    }
+
+    #[test]
+    fn extra_headers() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+        let headers = super::extra_headers(root, &["src/bin/*.rs".to_string()]).unwrap();
+        assert!(headers.contains(&root.join("src/bin/capi.rs")));
+        assert!(headers.iter().all(|p| p.extension() == Some("rs".as_ref())));
+
+        assert!(super::extra_headers(root, &["include/*.h".to_string()]).is_err());
+    }
+
+    #[test]
+    fn substitute() {
+        let mut vars = BTreeMap::new();
+        vars.insert("NAME".to_string(), "foo".to_string());
+        vars.insert("VERSION_MAJOR".to_string(), "1".to_string());
+
+        let (content, unknown) = super::substitute(
+            "#define @NAME@_MAJOR @VERSION_MAJOR@ /* foo@example.com @OTHER@ */",
+            &vars,
+        );
+
+        assert_eq!(content, "#define foo_MAJOR 1 /* foo@example.com @OTHER@ */");
+        assert_eq!(unknown, vec!["OTHER".to_string()]);
+    }
+
+    #[test]
+    fn feature_macros() {
+        let features = vec!["serde".to_string(), "foo-bar".to_string()]
+            .into_iter()
+            .collect();
+
+        assert_eq!(
+            super::feature_macros("FOO", &features),
+            "\n#define FOO_FEATURE_FOO_BAR 1\n#define FOO_FEATURE_SERDE 1\n"
+        );
+    }
+
+    #[test]
+    fn enabled_features() {
+        use cargo::core::Shell;
+
+        let root = std::env::temp_dir().join(format!("cargo-c-features-{}", std::process::id()));
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write(
+            "Cargo.toml",
+            r#"
+[package]
+name = "foo"
+version = "0.1.0"
+
+[features]
+default = ["std"]
+std = ["baz"]
+simd = ["baz/simd"]
+extra = []
+
+[dependencies]
+baz = { package = "bar", path = "bar", optional = true }
+"#,
+        );
+        write("src/lib.rs", "");
+        write(
+            "bar/Cargo.toml",
+            r#"
+[package]
+name = "bar"
+version = "0.1.0"
+
+[features]
+simd = []
+"#,
+        );
+        write("bar/src/lib.rs", "");
+
+        let config = Config::new(Shell::new(), root.clone(), root.clone());
+        let ws = Workspace::new(&root.join("Cargo.toml"), &config).unwrap();
+        let pkg = ws.current().unwrap();
+        let mut compile_opts = ops::CompileOptions::new(&config, CompileMode::Build).unwrap();
+
+        let set = |features: &[&str]| {
+            features
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<BTreeSet<_>>()
+        };
+
+        let enabled = super::enabled_features(&ws, pkg, &compile_opts).unwrap();
+        assert_eq!(enabled, set(&["baz", "default", "std"]));
+
+        compile_opts.no_default_features = true;
+        compile_opts.features = vec!["simd".into()];
+        let enabled = super::enabled_features(&ws, pkg, &compile_opts).unwrap();
+        assert_eq!(enabled, set(&["baz", "simd"]));
+
+        compile_opts.all_features = true;
+        let enabled = super::enabled_features(&ws, pkg, &compile_opts).unwrap();
+        assert_eq!(enabled, set(&["baz", "default", "extra", "simd", "std"]));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn defines() {
+        let target = target::Target {
+            arch: "x86_64".into(),
+            os: "linux".into(),
+            env: "gnu".into(),
+            verbatim: None,
+            triple: "x86_64-unknown-linux-gnu".into(),
+            cfg: ["unix", r#"target_os="linux""#, r#"target_family="unix""#]
+                .iter()
+                .map(|c| c.parse().unwrap())
+                .collect(),
+        };
+        let cfg = HeaderCfg {
+            package: "foo".into(),
+            release: false,
+            features: vec!["foo".to_string(), "bar".to_string()]
+                .into_iter()
+                .collect(),
+            enabled_features: vec!["foo".to_string()].into_iter().collect(),
+            target: &target,
+            version_rs: None,
+        };
+
+        let mut config = toml::value::Table::new();
+        cfg.apply_defines(&mut config, "FOO", false);
+        let defines = config["defines"].as_table().unwrap();
+
+        assert_eq!(defines["feature = bar"].as_str(), Some("FOO_FEATURE_BAR"));
+        assert!(!defines.contains_key("feature = foo"));
+        assert_eq!(defines["windows"].as_str(), Some("FOO_CFG_WINDOWS"));
+        assert_eq!(
+            defines["target_os = windows"].as_str(),
+            Some("FOO_CFG_TARGET_OS_WINDOWS")
+        );
+        assert!(!defines.contains_key("unix"));
+        assert!(!defines.contains_key("target_os = linux"));
+        assert!(!defines.contains_key("target_family = unix"));
+        assert!(!defines.contains_key("cargo_c"));
+
+        let mut config = toml::value::Table::new();
+        cfg.apply_defines(&mut config, "FOO", true);
+        let defines = config["defines"].as_table().unwrap();
+
+        assert_eq!(defines["feature = foo"].as_str(), Some("FOO_FEATURE_FOO"));
+    }
+
+    #[test]
+    fn version_macros() {
+        let mut config = HeaderVersionCApiConfig {
+            prefix: "FOO".into(),
+            version: Version::parse("1.2.3").unwrap(),
+            string: false,
+            packed: false,
+            check: false,
+            functions: false,
+        };
+
+        assert_eq!(
+            super::version_macros(&config),
+            "\n#define FOO_MAJOR 1\n#define FOO_MINOR 2\n#define FOO_PATCH 3\n"
+        );
+
+        config.string = true;
+        config.packed = true;
+        config.check = true;
+        let macros = super::version_macros(&config);
+
+        assert!(macros.contains("#define FOO_VERSION_STRING \"1.2.3\"\n"));
+        assert!(macros.contains("#define FOO_VERSION 0x010203\n"));
+        assert!(macros.contains("#define FOO_CHECK_VERSION(major, minor, patch) \\\n"));
+        assert!(!macros.contains("foo_version_major"));
+
+        config.functions = true;
+        let macros = super::version_macros(&config);
+        assert!(macros.contains("unsigned int foo_version_major(void);\n"));
+        assert!(macros.contains("const char *foo_version_string(void);\n"));
+
+        let source = super::version_functions(&config);
+        assert!(source.contains("fn foo_version_patch() -> std::os::raw::c_uint {\n    3\n}"));
+        assert!(source.contains("b\"1.2.3\\0\""));
+    }
+
+    #[test]
+    fn include_guard() {
+        let header = HeaderCApiConfig {
+            name: "my-lib".into(),
+            subdirectory: true,
+            generation: true,
+            version: HeaderVersionCApiConfig {
+                prefix: "FOO".into(),
+                version: Version::parse("1.2.3").unwrap(),
+                string: false,
+                packed: false,
+                check: false,
+                functions: false,
+            },
+            feature_macros: false,
+            cbindgen: None,
+            generator: HeaderGenerator::Builtin(BuiltinGenerator::Bundled),
+            files: Vec::new(),
+            umbrella: false,
+            extra: Vec::new(),
+            variables: BTreeMap::new(),
+        };
+
+        let config = super::cbindgen_config(&header, Path::new("/nonexistent")).unwrap();
+        assert_eq!(config["include_guard"].as_str(), Some("MY_LIB_H"));
+    }
+}
@@ -1,16 +1,26 @@
 use std::path::PathBuf;
 
+use serde_derive::Serialize;
+
 use crate::build::CApiConfig;
+use crate::error::Error;
 use crate::target::Target;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct BuildTargets {
-    pub include: PathBuf,
+    /// Headers, the umbrella one first
+    pub include: Vec<PathBuf>,
+    /// Hand-written headers, installed as they are
+    pub extra_headers: Vec<PathBuf>,
     pub static_lib: Option<PathBuf>,
     pub shared_lib: Option<PathBuf>,
     pub impl_lib: Option<PathBuf>,
     pub def: Option<PathBuf>,
     pub pc: PathBuf,
+    /// pkg-config file pointing at the build directory
+    pub pc_uninstalled: PathBuf,
+    /// pkg-config file linking the static library by default
+    pub pc_static: Option<PathBuf>,
 }
 
 impl BuildTargets {
@@ -20,11 +30,32 @@ impl BuildTargets {
         targetdir: &PathBuf,
         libkinds: &[&str],
         capi_config: &CApiConfig,
-    ) -> BuildTargets {
+    ) -> Result<BuildTargets, Error> {
         let pc = targetdir.join(&format!("{}.pc", name));
-        let mut header_name = PathBuf::from(&capi_config.header.name);
+        let pc_uninstalled = targetdir.join(&format!("{}-uninstalled.pc", name));
+        let header = &capi_config.header;
+        let mut header_name = PathBuf::from(&header.name);
         header_name.set_extension("h");
-        let include = targetdir.join(&header_name);
+
+        // Split headers are kept apart since their names may clash across packages
+        let include = if header.files.is_empty() {
+            vec![targetdir.join(&header_name)]
+        } else {
+            let dir = targetdir
+                .join("include")
+                .join(header.name.trim_end_matches(".h"));
+            let mut include = Vec::new();
+            if header.umbrella {
+                include.push(dir.join(&header_name));
+            }
+            include.extend(
+                header
+                    .files
+                    .iter()
+                    .map(|f| dir.join(format!("{}.h", f.name))),
+            );
+            include
+        };
 
         let lib_name = &capi_config.library.name;
 
@@ -57,7 +88,7 @@ impl BuildTargets {
                 let def = targetdir.join(&format!("{}.def", lib_name));
                 (shared_lib, static_lib, Some(impl_lib), Some(def))
             }
-            _ => unimplemented!("The target {}-{} is not supported yet", os, env),
+            _ => return Err(Error::UnsupportedTarget(format!("{}-{}", os, env))),
         };
 
         let static_lib = if libkinds.contains(&"staticlib") {
@@ -71,13 +102,46 @@ impl BuildTargets {
             None
         };
 
-        BuildTargets {
+        let pc_static = if static_lib.is_some() && capi_config.pkg_config.static_pc {
+            Some(targetdir.join(&format!("{}-static.pc", name)))
+        } else {
+            None
+        };
+
+        Ok(BuildTargets {
             pc,
+            pc_uninstalled,
+            pc_static,
             include,
+            extra_headers: Vec::new(),
             static_lib,
             shared_lib,
             impl_lib,
             def,
+        })
+    }
+
+    /// All the artifacts produced, along with their kind
+    pub fn artifacts(&self) -> Vec<(&'static str, &PathBuf)> {
+        let mut artifacts: Vec<_> = self
+            .include
+            .iter()
+            .chain(self.extra_headers.iter())
+            .map(|p| ("header", p))
+            .collect();
+        artifacts.push(("pc", &self.pc));
+        artifacts.push(("pc-uninstalled", &self.pc_uninstalled));
+        artifacts.extend(self.pc_static.iter().map(|p| ("pc-static", p)));
+
+        artifacts.extend(self.static_lib.iter().map(|p| ("static-lib", p)));
+
+        // The import library and the def file only exist along with the shared library
+        if let Some(shared_lib) = self.shared_lib.as_ref() {
+            artifacts.push(("shared-lib", shared_lib));
+            artifacts.extend(self.impl_lib.iter().map(|p| ("impl-lib", p)));
+            artifacts.extend(self.def.iter().map(|p| ("def", p)));
         }
+
+        artifacts
     }
 }
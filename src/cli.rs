@@ -16,6 +16,10 @@ struct Common {
     /// includedir, libdir, bindir, pkgconfigdir
     #[structopt(long = "prefix", parse(from_os_str))]
     prefix: Option<PathBuf>,
+    /// directory path used to construct default values of
+    /// libdir and bindir, defaults to the prefix
+    #[structopt(long = "exec-prefix", parse(from_os_str))]
+    exec_prefix: Option<PathBuf>,
     /// path to directory for installing generated library files
     #[structopt(long = "libdir", parse(from_os_str))]
     libdir: Option<PathBuf>,
@@ -75,19 +79,18 @@ pub fn subcommand_cli(name: &str, about: &'static str) -> App<'static, 'static>
     base_cli()
         .name(name)
         .about(about)
+        .arg_package_spec(
+            "Package(s) to build and install",
+            "Build and install all the C-API packages in the workspace",
+            "Exclude packages from the build",
+        )
         .arg_jobs()
         .arg_release("Build artifacts in release mode, with optimizations")
         .arg_profile("Build artifacts with the specified profile")
         .arg_features()
         .arg_target_triple("Build for the target triple")
         .arg_target_dir()
-        .arg(
-            opt(
-                "out-dir",
-                "Copy final artifacts to this directory (unstable)",
-            )
-            .value_name("PATH"),
-        )
+        .arg(opt("out-dir", "Copy final artifacts to this directory").value_name("PATH"))
         .arg_manifest_path()
         .arg_message_format()
         .arg_build_plan()
@@ -0,0 +1,71 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Errors reported by cargo-c
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The target, or one of its components, is not supported
+    #[error("The target {0} is not supported yet")]
+    UnsupportedTarget(String),
+    /// cbindgen or the header generator command could not generate the header
+    #[error("Cannot generate the header from {location}: {message}")]
+    HeaderGeneration { message: String, location: String },
+    /// More than one package could be built and none was selected
+    #[error(
+        "Multiple packages found, select one with `--package` or use `--workspace`.\n\
+         Possible packages: {candidates}"
+    )]
+    AmbiguousPackage { candidates: String },
+    /// The selected package does not provide a library
+    #[error("Package `{package}` has no lib target.\nPossible packages: {candidates}")]
+    MissingLibTarget { package: String, candidates: String },
+    /// An external tool could not be run or exited with an error
+    #[error("Command {command} failed\n{stderr}")]
+    ToolInvocation { command: String, stderr: String },
+    /// A pkg-config file could not be parsed or is not valid
+    #[error("Invalid pkg-config file {}:\n  {}", .path.display(), .problems.join("\n  "))]
+    InvalidPkgConfig {
+        path: PathBuf,
+        problems: Vec<String>,
+    },
+    /// A file could not be installed
+    #[error("Cannot install {} to {}", .from.display(), .to.display())]
+    Install {
+        from: PathBuf,
+        to: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Run `cmd` to completion, failing if it cannot be spawned or if it exits
+/// with an error
+pub(crate) fn run(cmd: &mut Command) -> Result<Output, Error> {
+    let out = cmd.output().map_err(|err| Error::ToolInvocation {
+        command: format!("{:?}", cmd),
+        stderr: err.to_string(),
+    })?;
+
+    if out.status.success() {
+        Ok(out)
+    } else {
+        Err(Error::ToolInvocation {
+            command: format!("{:?}", cmd),
+            stderr: String::from_utf8_lossy(&out.stderr).into_owned(),
+        })
+    }
+}
+
+impl Error {
+    pub(crate) fn header_generation(err: cbindgen::Error, manifest_path: &Path) -> Self {
+        let location = match err {
+            cbindgen::Error::ParseSyntaxError { ref src_path, .. }
+            | cbindgen::Error::ParseCannotOpenFile { ref src_path, .. } => src_path.clone(),
+            _ => manifest_path.display().to_string(),
+        };
+
+        Error::HeaderGeneration {
+            message: err.to_string(),
+            location,
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::{Path, PathBuf};
+
+use cargo::core::Workspace;
+use cargo::Config;
+
+use crate::api::CApiBuild;
+use crate::build::{
+    build_headers, build_version_functions_file, patch_capi_feature, resolve_packages, root_output,
+};
+use crate::target::Target;
+
+/// What `cargo capi header` does with the headers committed in `assets`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderMode {
+    /// Print the differences, failing if there is any
+    Check,
+    /// Rewrite the out of date headers
+    Update,
+}
+
+/// Unified diff from the committed header to the generated one, `path` is
+/// relative to the package root so it applies with `patch -p1`
+fn diff(path: &Path, old: &str, new: &str) -> String {
+    let old_name = format!("a/{}", path.display());
+    let new_name = format!("b/{}", path.display());
+
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&old_name, &new_name)
+        .to_string()
+}
+
+/// Generate the headers as `cbuild` would and compare them with the ones in
+/// the `assets` directory
+pub(crate) fn cheader(
+    ws: &mut Workspace,
+    config: &Config,
+    build: &CApiBuild,
+    mode: HeaderMode,
+) -> anyhow::Result<()> {
+    let rustc_target = Target::new(build.target.as_ref())?;
+
+    let mut compile_opts = build.compile_options(config, ws)?;
+
+    let root_output = root_output(ws, config, build.target.as_deref(), &compile_opts)?;
+
+    let packages = resolve_packages(ws, config, build, &rustc_target, &root_output)?;
+
+    let features = compile_opts.features.clone();
+    let mut stale = Vec::new();
+
+    for cpkg in packages.iter() {
+        let pkg = ws
+            .members()
+            .find(|p| p.package_id() == cpkg.package_id)
+            .unwrap();
+
+        let header = &cpkg.capi_config.header;
+        if !header.generation {
+            anyhow::bail!(
+                "The header of `{}` is not generated, there is nothing to compare",
+                cpkg.name
+            );
+        }
+
+        compile_opts.features = features.clone();
+        patch_capi_feature(&mut compile_opts, pkg)?;
+
+        let version_rs = build_version_functions_file(&cpkg.name, header, &root_output)?;
+
+        let out_dir = root_output.join("capi-header").join(&cpkg.name);
+        let include: Vec<PathBuf> = cpkg
+            .build_targets
+            .include
+            .iter()
+            .map(|p| out_dir.join(p.file_name().unwrap_or_default()))
+            .collect();
+
+        build_headers(
+            ws,
+            pkg,
+            cpkg,
+            &compile_opts,
+            &rustc_target,
+            &include,
+            version_rs.as_deref(),
+        )?;
+
+        for generated in include.iter() {
+            let asset = pkg
+                .root()
+                .join("assets")
+                .join(generated.file_name().unwrap_or_default());
+
+            let new = std::fs::read_to_string(generated)?;
+            let old = std::fs::read_to_string(&asset).unwrap_or_default();
+            if old == new {
+                continue;
+            }
+
+            match mode {
+                HeaderMode::Check => {
+                    let rel = asset.strip_prefix(pkg.root()).unwrap_or(&asset);
+                    print!("{}", diff(rel, &old, &new));
+                    stale.push(asset.display().to_string());
+                }
+                HeaderMode::Update => {
+                    config.shell().status("Updating", asset.display())?;
+                    std::fs::create_dir_all(pkg.root().join("assets"))?;
+                    std::fs::write(&asset, new)?;
+                }
+            }
+        }
+    }
+
+    if !stale.is_empty() {
+        anyhow::bail!(
+            "Out of date headers: {}\nRun `cargo capi header --update` to update them",
+            stale.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[test]
+    fn diff() {
+        let diff = super::diff(
+            Path::new("assets/foo.h"),
+            "#define FOO_MAJOR 0\n#define FOO_MINOR 1\n",
+            "#define FOO_MAJOR 0\n#define FOO_MINOR 2\n",
+        );
+
+        assert_eq!(
+            diff,
+            "--- a/assets/foo.h\n\
+             +++ b/assets/foo.h\n\
+             @@ -1,2 +1,2 @@\n \
+             #define FOO_MAJOR 0\n\
+             -#define FOO_MINOR 1\n\
+             +#define FOO_MINOR 2\n"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use cargo::core::Workspace;
+use cargo::Config;
+
+use serde_derive::Serialize;
+
+use crate::api::CApiBuild;
+use crate::build::{resolve_packages, root_output, CPackage};
+use crate::target::Target;
+
+/// Fully resolved configuration of the selected packages
+#[derive(Debug, Serialize)]
+pub struct CInfo {
+    pub target: Target,
+    pub packages: Vec<CPackage>,
+}
+
+impl CInfo {
+    /// Render the information as `json` or, by default, as `toml`
+    pub fn render(&self, format: &str) -> anyhow::Result<String> {
+        let out = match format {
+            "json" => serde_json::to_string_pretty(self)?,
+            _ => toml::to_string_pretty(&toml::Value::try_from(self)?)?,
+        };
+
+        Ok(out)
+    }
+}
+
+/// Resolve the configuration `cbuild` would use, without compiling anything
+pub(crate) fn cinfo(
+    ws: &mut Workspace,
+    config: &Config,
+    build: &CApiBuild,
+) -> anyhow::Result<CInfo> {
+    let rustc_target = Target::new(build.target.as_ref())?;
+
+    let compile_opts = build.compile_options(config, ws)?;
+
+    let root_output = root_output(ws, config, build.target.as_deref(), &compile_opts)?;
+
+    let packages = resolve_packages(ws, config, build, &rustc_target, &root_output)?;
+
+    Ok(CInfo {
+        target: rustc_target,
+        packages,
+    })
+}
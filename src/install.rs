@@ -1,14 +1,16 @@
+use std::collections::BTreeMap;
 use std::path::{Component, Path, PathBuf};
-use structopt::clap::ArgMatches;
 
-use cargo::core::Workspace;
+use cargo::Config;
+use serde::de::IgnoredAny;
+use serde_derive::Serialize;
 
 use crate::build::CApiConfig;
 use crate::build_targets::BuildTargets;
+use crate::error::Error;
+use crate::pkg_config_gen::check_pc_file;
 use crate::target::Target;
 
-use anyhow::Context;
-
 fn append_to_destdir(destdir: &PathBuf, path: &PathBuf) -> PathBuf {
     let mut joined = destdir.clone();
     for component in path.components() {
@@ -66,27 +68,114 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn install_paths_precedence() {
+        use cargo::core::Shell;
+        use cargo::Config;
+
+        use super::{InstallOptions, InstallPaths, PathOrigin};
+
+        let root =
+            std::env::temp_dir().join(format!("cargo-c-install-paths-{}", std::process::id()));
+        std::fs::create_dir_all(root.join(".cargo")).unwrap();
+        std::fs::write(
+            root.join(".cargo").join("config"),
+            r#"
+[capi]
+prefix = "/config"
+bindir = "/config/bin"
+pkgconfigdir = "/config/pkgconfig"
+lib_dir = "/config/lib"
+
+[capi.install]
+libdir = "lib64"
+includedir = "/config/include"
+pkgconfigdir = "/config/install/pkgconfig"
+"#,
+        )
+        .unwrap();
+        let config = Config::new(Shell::new(), root.clone(), root.clone());
+
+        let env = [("includedir", PathBuf::from("/env/include"))]
+            .iter()
+            .cloned()
+            .collect();
+        let options = InstallOptions {
+            prefix: Some("/cli".into()),
+            ..Default::default()
+        };
+        let paths = InstallPaths::with_subdirectory("foo", &options, true, &env, &config).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(paths.prefix, PathBuf::from("/cli"));
+        assert_eq!(paths.exec_prefix, PathBuf::from("/cli"));
+        assert_eq!(paths.libdir, PathBuf::from("/cli/lib64"));
+        assert_eq!(paths.includedir, PathBuf::from("/env/include/foo"));
+        assert_eq!(paths.bindir, PathBuf::from("/config/bin"));
+        assert_eq!(
+            paths.pkgconfigdir,
+            PathBuf::from("/config/install/pkgconfig")
+        );
+
+        let origin = |key: &str| paths.origins[key];
+        assert_eq!(origin("prefix"), PathOrigin::CommandLine);
+        assert_eq!(origin("includedir"), PathOrigin::Environment);
+        assert_eq!(origin("libdir"), PathOrigin::Config);
+        assert_eq!(origin("bindir"), PathOrigin::Config);
+        assert_eq!(origin("exec_prefix"), PathOrigin::Default);
+    }
+}
+
+/// Create the install directory `to` for the files built in `from`
+fn create_dir_all(from: &Path, to: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(to).map_err(|source| Error::Install {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+        source,
+    })
 }
 
-fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> anyhow::Result<u64> {
+fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<u64, Error> {
     let from = from.as_ref();
     let to = to.as_ref();
-    std::fs::copy(from, to)
-        .with_context(|| format!("Cannot copy {} to {}.", from.display(), to.display()))
+    std::fs::copy(from, to).map_err(|source| Error::Install {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+        source,
+    })
+}
+
+/// Copy `from` inside the directory `dir`, keeping its file name
+fn copy_into(from: &Path, dir: &Path) -> Result<u64, Error> {
+    let name = from.file_name().unwrap_or_default();
+    copy(from, dir.join(name))
+}
+
+fn ln_sf(target: &str, link: &Path) -> Result<(), Error> {
+    let mut cmd = std::process::Command::new("ln");
+    cmd.arg("-sf").arg(target).arg(link);
+    crate::error::run(&mut cmd)?;
+
+    Ok(())
 }
 
 pub fn cinstall(
-    ws: &Workspace,
+    config: &Config,
     target: &Target,
     capi_config: &CApiConfig,
-    build_targets: BuildTargets,
-    paths: InstallPaths,
+    build_targets: &BuildTargets,
+    paths: &InstallPaths,
 ) -> anyhow::Result<()> {
-    use std::fs;
-
     let os = &target.os;
     let env = &target.env;
 
+    // Refuse to install a pkg-config file pkg-config cannot use
+    check_pc_file(&build_targets.pc)?;
+    if let Some(ref pc_static) = build_targets.pc_static {
+        check_pc_file(pc_static)?;
+    }
+
     let destdir = &paths.destdir;
 
     let install_path_lib = append_to_destdir(destdir, &paths.libdir);
@@ -94,50 +183,45 @@ pub fn cinstall(
     let install_path_include = append_to_destdir(destdir, &paths.includedir);
     let install_path_bin = append_to_destdir(destdir, &paths.bindir);
 
-    fs::create_dir_all(&install_path_lib)?;
-    fs::create_dir_all(&install_path_pc)?;
-    fs::create_dir_all(&install_path_include)?;
-    fs::create_dir_all(&install_path_bin)?;
-
-    ws.config()
-        .shell()
-        .status("Installing", "pkg-config file")?;
-    fs::copy(
-        &build_targets.pc,
-        install_path_pc.join(build_targets.pc.file_name().unwrap()),
-    )?;
-    ws.config().shell().status("Installing", "header file")?;
-    fs::copy(
-        &build_targets.include,
-        install_path_include.join(build_targets.include.file_name().unwrap()),
-    )?;
+    let build_dir = build_targets.pc.parent().unwrap_or_else(|| Path::new(""));
+    create_dir_all(build_dir, &install_path_lib)?;
+    create_dir_all(build_dir, &install_path_pc)?;
+    create_dir_all(build_dir, &install_path_include)?;
+    create_dir_all(build_dir, &install_path_bin)?;
+
+    config.shell().status("Installing", "pkg-config file")?;
+    copy_into(&build_targets.pc, &install_path_pc)?;
+    if let Some(ref pc_static) = build_targets.pc_static {
+        copy_into(pc_static, &install_path_pc)?;
+    }
+    config.shell().status("Installing", "header file")?;
+    for include in build_targets
+        .include
+        .iter()
+        .chain(build_targets.extra_headers.iter())
+    {
+        copy_into(include, &install_path_include)?;
+    }
 
     if let Some(ref static_lib) = build_targets.static_lib {
-        ws.config().shell().status("Installing", "static library")?;
-        copy(
-            static_lib,
-            install_path_lib.join(static_lib.file_name().unwrap()),
-        )?;
+        config.shell().status("Installing", "static library")?;
+        copy_into(static_lib, &install_path_lib)?;
     }
 
     if let Some(ref shared_lib) = build_targets.shared_lib {
-        ws.config().shell().status("Installing", "shared library")?;
+        config.shell().status("Installing", "shared library")?;
 
         let lib_name = &capi_config.library.name;
         let lib_version = &capi_config.library.version;
 
-        let link_libs = |lib: &str, lib_with_major_ver: &str, lib_with_full_ver: &str| {
-            let mut ln_sf = std::process::Command::new("ln");
-            ln_sf.arg("-sf");
-            ln_sf
-                .arg(lib_with_full_ver)
-                .arg(install_path_lib.join(lib_with_major_ver));
-            let _ = ln_sf.status().unwrap();
-            let mut ln_sf = std::process::Command::new("ln");
-            ln_sf.arg("-sf");
-            ln_sf.arg(lib_with_full_ver).arg(install_path_lib.join(lib));
-            let _ = ln_sf.status().unwrap();
-        };
+        let link_libs =
+            |lib: &str, lib_with_major_ver: &str, lib_with_full_ver: &str| -> Result<(), Error> {
+                ln_sf(
+                    lib_with_full_ver,
+                    &install_path_lib.join(lib_with_major_ver),
+                )?;
+                ln_sf(lib_with_full_ver, &install_path_lib.join(lib))
+            };
 
         match (os.as_str(), env.as_str()) {
             ("linux", _) | ("freebsd", _) | ("dragonfly", _) | ("netbsd", _) => {
@@ -148,7 +232,7 @@ pub fn cinstall(
                     lib_with_major_ver, lib_version.minor, lib_version.patch
                 );
                 copy(shared_lib, install_path_lib.join(lib_with_full_ver))?;
-                link_libs(lib, lib_with_major_ver, lib_with_full_ver);
+                link_libs(lib, lib_with_major_ver, lib_with_full_ver)?;
             }
             ("macos", _) => {
                 let lib = &format!("lib{}.dylib", lib_name);
@@ -158,72 +242,206 @@ pub fn cinstall(
                     lib_name, lib_version.major, lib_version.minor, lib_version.patch
                 );
                 copy(shared_lib, install_path_lib.join(lib_with_full_ver))?;
-                link_libs(lib, lib_with_major_ver, lib_with_full_ver);
+                link_libs(lib, lib_with_major_ver, lib_with_full_ver)?;
             }
             ("windows", _) => {
-                let lib_name = shared_lib.file_name().unwrap();
-                copy(shared_lib, install_path_bin.join(lib_name))?;
-                let impl_lib = build_targets.impl_lib.as_ref().unwrap();
-                let impl_lib_name = impl_lib.file_name().unwrap();
-                copy(impl_lib, install_path_lib.join(impl_lib_name))?;
-                let def = build_targets.def.as_ref().unwrap();
-                let def_name = def.file_name().unwrap();
-                copy(def, install_path_lib.join(def_name))?;
+                copy_into(shared_lib, &install_path_bin)?;
+                if let Some(ref impl_lib) = build_targets.impl_lib {
+                    copy_into(impl_lib, &install_path_lib)?;
+                }
+                if let Some(ref def) = build_targets.def {
+                    copy_into(def, &install_path_lib)?;
+                }
             }
-            _ => unimplemented!("The target {}-{} is not supported yet", os, env),
+            _ => return Err(Error::UnsupportedTarget(format!("{}-{}", os, env)).into()),
         }
     }
 
     Ok(())
 }
 
-#[derive(Debug)]
+/// Where an install path was taken from, from the highest to the lowest
+/// precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PathOrigin {
+    /// Set on the command line or through `CApiBuild`
+    CommandLine,
+    Environment,
+    Config,
+    Default,
+}
+
+/// Install paths set explicitly, they take precedence over the environment
+/// and the cargo configuration.
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    pub destdir: Option<PathBuf>,
+    pub prefix: Option<PathBuf>,
+    pub exec_prefix: Option<PathBuf>,
+    pub libdir: Option<PathBuf>,
+    pub includedir: Option<PathBuf>,
+    pub bindir: Option<PathBuf>,
+    pub pkgconfigdir: Option<PathBuf>,
+}
+
+/// The install paths that may be set in the cargo configuration
+const INSTALL_KEYS: &[&str] = &[
+    "destdir",
+    "prefix",
+    "exec_prefix",
+    "libdir",
+    "includedir",
+    "bindir",
+    "pkgconfigdir",
+];
+
+/// Read the install paths from the `CARGO_C_<NAME>` environment variables
+fn install_env() -> BTreeMap<&'static str, PathBuf> {
+    INSTALL_KEYS
+        .iter()
+        .filter_map(|key| {
+            std::env::var_os(format!("CARGO_C_{}", key.to_uppercase()))
+                .map(|v| (*key, PathBuf::from(v)))
+        })
+        .collect()
+}
+
+/// Read the install paths from the `[capi]` and `[capi.install]` cargo
+/// configuration sections, the latter taking precedence.
+fn install_config(config: &Config) -> anyhow::Result<BTreeMap<&'static str, PathBuf>> {
+    let mut paths = BTreeMap::new();
+
+    for section in &["capi", "capi.install"] {
+        let keys = config
+            .get::<Option<BTreeMap<String, IgnoredAny>>>(section)?
+            .unwrap_or_default();
+
+        for key in keys.keys() {
+            if *section == "capi" && key == "install" {
+                continue;
+            }
+            match INSTALL_KEYS.iter().find(|k| *k == key) {
+                Some(k) => {
+                    let path = config.get::<PathBuf>(&format!("{}.{}", section, key))?;
+                    paths.insert(*k, path);
+                }
+                None => config
+                    .shell()
+                    .warn(format!("unused config key `{}.{}`", section, key))?,
+            }
+        }
+    }
+
+    Ok(paths)
+}
+
+impl InstallOptions {
+    fn get(&self, key: &str) -> Option<&PathBuf> {
+        match key {
+            "destdir" => self.destdir.as_ref(),
+            "prefix" => self.prefix.as_ref(),
+            "exec_prefix" => self.exec_prefix.as_ref(),
+            "libdir" => self.libdir.as_ref(),
+            "includedir" => self.includedir.as_ref(),
+            "bindir" => self.bindir.as_ref(),
+            "pkgconfigdir" => self.pkgconfigdir.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct InstallPaths {
     pub destdir: PathBuf,
     pub prefix: PathBuf,
+    pub exec_prefix: PathBuf,
     pub libdir: PathBuf,
     pub includedir: PathBuf,
     pub bindir: PathBuf,
     pub pkgconfigdir: PathBuf,
+    pub origins: BTreeMap<&'static str, PathOrigin>,
 }
 
 impl InstallPaths {
-    pub fn new(name: &str, args: &ArgMatches<'_>, capi_config: &CApiConfig) -> Self {
-        let destdir = args
-            .value_of("destdir")
-            .map(PathBuf::from)
-            .unwrap_or_else(|| PathBuf::from("/"));
-        let prefix = args
-            .value_of("prefix")
-            .map(PathBuf::from)
-            .unwrap_or_else(|| "/usr/local".into());
-        let libdir = args
-            .value_of("libdir")
-            .map(PathBuf::from)
-            .unwrap_or_else(|| prefix.join("lib"));
-        let mut includedir = args
-            .value_of("includedir")
-            .map(PathBuf::from)
-            .unwrap_or_else(|| prefix.join("include"));
-        if capi_config.header.subdirectory {
+    /// Resolve the install paths.
+    ///
+    /// Each path is taken from `options`, the `CARGO_C_<NAME>` environment
+    /// variable or the `[capi.install]` and `[capi]` cargo configuration
+    /// sections, in this order, and falls back to a default derived from the
+    /// prefix. Relative directories are relative to the prefix.
+    pub fn new(
+        name: &str,
+        options: &InstallOptions,
+        capi_config: &CApiConfig,
+        config: &Config,
+    ) -> anyhow::Result<Self> {
+        Self::with_subdirectory(
+            name,
+            options,
+            capi_config.header.subdirectory,
+            &install_env(),
+            config,
+        )
+    }
+
+    /// Resolve the install paths with the `CARGO_C_<NAME>` variables in `env`
+    fn with_subdirectory(
+        name: &str,
+        options: &InstallOptions,
+        subdirectory: bool,
+        env: &BTreeMap<&str, PathBuf>,
+        config: &Config,
+    ) -> anyhow::Result<Self> {
+        let install_config = install_config(config)?;
+
+        let lookup = |key: &str| {
+            options
+                .get(key)
+                .map(|v| (v.clone(), PathOrigin::CommandLine))
+                .or_else(|| env.get(key).map(|v| (v.clone(), PathOrigin::Environment)))
+                .or_else(|| {
+                    install_config
+                        .get(key)
+                        .map(|v| (v.clone(), PathOrigin::Config))
+                })
+        };
+
+        let mut origins = BTreeMap::new();
+        let mut resolve = |key: &'static str, default: PathBuf| {
+            let (path, origin) = lookup(key).unwrap_or((default, PathOrigin::Default));
+            origins.insert(key, origin);
+            path
+        };
+
+        let destdir = resolve("destdir", PathBuf::from("/"));
+        let prefix = resolve("prefix", PathBuf::from("/usr/local"));
+        let exec_prefix = prefix.join(resolve("exec_prefix", prefix.clone()));
+        let libdir = prefix.join(resolve("libdir", exec_prefix.join("lib")));
+        let mut includedir = prefix.join(resolve("includedir", prefix.join("include")));
+        if subdirectory {
             includedir = includedir.join(name);
         }
-        let bindir = args
-            .value_of("bindir")
-            .map(PathBuf::from)
-            .unwrap_or_else(|| prefix.join("bin"));
-        let pkgconfigdir = args
-            .value_of("pkgconfigdir")
-            .map(PathBuf::from)
-            .unwrap_or_else(|| libdir.join("pkgconfig"));
-
-        InstallPaths {
+        let bindir = prefix.join(resolve("bindir", exec_prefix.join("bin")));
+        let pkgconfigdir = prefix.join(resolve("pkgconfigdir", libdir.join("pkgconfig")));
+
+        Ok(InstallPaths {
             destdir,
             prefix,
+            exec_prefix,
             libdir,
             includedir,
             bindir,
             pkgconfigdir,
-        }
+            origins,
+        })
+    }
+
+    /// Whether the path `key` was set explicitly instead of being derived
+    /// from the prefix.
+    pub fn is_explicit(&self, key: &str) -> bool {
+        self.origins
+            .get(key)
+            .map_or(false, |&origin| origin != PathOrigin::Default)
     }
 }
@@ -1,7 +1,14 @@
+pub mod api;
 pub mod build;
 pub mod build_targets;
 pub mod cli;
+pub mod error;
+pub mod header;
+pub mod info;
 pub mod install;
+pub mod metadata;
 pub mod pkg_config_gen;
 pub mod static_libs;
 pub mod target;
+
+pub use crate::error::Error;
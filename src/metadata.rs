@@ -0,0 +1,669 @@
+//! Typed representation of the `package.metadata.capi` manifest section.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use cargo_platform::{Cfg, Platform};
+use schemars::JsonSchema;
+use semver::Version;
+use serde::Deserializer;
+use serde_derive::{Deserialize, Serialize};
+
+/// Key path of the package section, used to report warnings and errors
+pub const CAPI_KEY: &str = "package.metadata.capi";
+/// Key path of the workspace section providing the package defaults
+pub const WORKSPACE_CAPI_KEY: &str = "workspace.metadata.capi";
+
+/// Settings under `package.metadata.capi`
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CApiMetadata {
+    /// Minimum required cargo-c version. Trying to run with an older
+    /// version causes an error.
+    #[serde(deserialize_with = "deserialize_version")]
+    #[schemars(with = "Option<String>")]
+    pub min_version: Option<Version>,
+    /// Deprecated, use `header.name` instead.
+    pub header_name: Option<String>,
+    /// Header generation settings
+    pub header: HeaderMetadata,
+    /// pkg-config file generation settings
+    pub pkg_config: PkgConfigMetadata,
+    /// Library generation settings
+    pub library: LibraryMetadata,
+    /// Per-target overrides, keyed by `cfg(...)` expression or target triple
+    pub target: BTreeMap<String, TargetMetadata>,
+}
+
+/// Overrides under `package.metadata.capi.target.<cfg or triple>`
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct TargetMetadata {
+    /// Header generation settings
+    pub header: HeaderMetadata,
+    /// pkg-config file generation settings
+    pub pkg_config: PkgConfigMetadata,
+    /// Library generation settings
+    pub library: LibraryMetadata,
+}
+
+/// Settings under `package.metadata.capi.header`
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HeaderMetadata {
+    /// Header file name, with or without the `.h` extension.
+    /// Defaults to the crate name.
+    pub name: Option<String>,
+    /// Install the header into a subdirectory with the name of the crate.
+    /// Defaults to `true`.
+    pub subdirectory: Option<bool>,
+    /// Generate the header with cbindgen or copy a pre-generated one from
+    /// the `assets` directory. Defaults to `true`.
+    pub generation: Option<bool>,
+    /// Version macros defined in the generated header
+    pub version: HeaderVersionMetadata,
+    /// Define `<PREFIX>_FEATURE_<NAME>` for every enabled cargo feature and
+    /// guard the feature-gated items with it. Defaults to `false`.
+    pub feature_macros: Option<bool>,
+    /// cbindgen configuration, merged over `cbindgen.toml`
+    #[schemars(with = "Option<BTreeMap<String, serde_json::Value>>")]
+    pub cbindgen: Option<toml::Value>,
+    /// Header generation backend, defaults to `bundled`.
+    pub generator: Option<HeaderGenerator>,
+    /// Split the C-API in multiple headers, installed in the header
+    /// subdirectory.
+    pub files: Vec<HeaderFileMetadata>,
+    /// Generate `<name>.h` including all the `files`. Defaults to `false`.
+    pub umbrella: Option<bool>,
+    /// Glob patterns, relative to the crate root, of hand-written headers
+    /// installed along with the generated ones.
+    pub extra: Vec<String>,
+    /// Variables substituted in the `assets/<name>.h.in` templates, along
+    /// with `NAME`, `PREFIX`, `VERSION` and `VERSION_MAJOR/MINOR/PATCH`.
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Settings of every entry of `package.metadata.capi.header.files`
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct HeaderFileMetadata {
+    /// Header file name, with or without the `.h` extension.
+    pub name: String,
+    /// Generate the header from this module only, e.g. `codec::h264`.
+    #[serde(default)]
+    pub module: Option<String>,
+    /// Items added to the cbindgen `export.include` list.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Items added to the cbindgen `export.exclude` list.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// How the header is generated
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum HeaderGenerator {
+    /// `bundled` or `cbindgen-cli`
+    Builtin(BuiltinGenerator),
+    /// Custom command, run from the crate directory. It writes the header
+    /// to `$CARGO_C_HEADER_OUTPUT`, or to `output` when set.
+    Command {
+        command: Vec<String>,
+        #[serde(default)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Generators cargo-c knows about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuiltinGenerator {
+    /// The cbindgen library cargo-c is built with
+    Bundled,
+    /// The `cbindgen` binary found in `PATH`, or `$CBINDGEN`
+    CbindgenCli,
+}
+
+/// Settings under `package.metadata.capi.header.version`
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HeaderVersionMetadata {
+    /// Prefix of the version macros, defaults to the uppercased header name.
+    pub prefix: Option<String>,
+    /// Version exposed by the macros, defaults to `crate`.
+    pub source: Option<VersionSource>,
+    /// Define `<PREFIX>_VERSION_STRING`. Defaults to `false`.
+    pub string: Option<bool>,
+    /// Define `<PREFIX>_VERSION` as the packed hex integer
+    /// `major << 16 | minor << 8 | patch`. Defaults to `false`.
+    pub packed: Option<bool>,
+    /// Define the `<PREFIX>_CHECK_VERSION(major, minor, patch)` macro.
+    /// Defaults to `false`.
+    pub check: Option<bool>,
+    /// Declare the `<prefix>_version_major/minor/patch/string()` functions
+    /// the crate exports with `include!(env!("CARGO_C_VERSION_RS"))`.
+    /// Defaults to `false`.
+    pub functions: Option<bool>,
+}
+
+/// Version exposed in the generated header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionSource {
+    /// The crate version
+    Crate,
+    /// `library.version`
+    Library,
+}
+
+/// Settings under `package.metadata.capi.pkg_config`
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct PkgConfigMetadata {
+    /// Package name, defaults to the crate name.
+    pub name: Option<String>,
+    /// Package description, defaults to the crate description.
+    pub description: Option<String>,
+    /// Package version, defaults to the crate version.
+    pub version: Option<String>,
+    /// `Requires` entries, e.g. `glib-2.0 >= 2.40`.
+    pub requires: Vec<String>,
+    /// `Requires.private` entries.
+    pub requires_private: Vec<String>,
+    /// `Conflicts` entries.
+    pub conflicts: Vec<String>,
+    /// Package URL, defaults to the crate homepage or repository.
+    pub url: Option<String>,
+    /// Entries added to `Libs`, e.g. `-llog`.
+    pub libs: Vec<String>,
+    /// Entries added to `Libs.private`.
+    pub libs_private: Vec<String>,
+    /// Entries added to `Cflags`.
+    pub cflags: Vec<String>,
+    /// Make `prefix` relative to the location of the installed file and the
+    /// other directories relative to `prefix`. Defaults to `false`.
+    pub relocatable: Option<bool>,
+    /// Flags needed to use the static library, e.g. `-DFOO_STATIC`,
+    /// rendered as `Cflags.private`.
+    pub static_cflags: Vec<String>,
+    /// Also generate `<name>-static.pc`, linking the static library and its
+    /// native dependencies by default. Defaults to `false`.
+    pub static_pc: Option<bool>,
+    /// Variables defined after `prefix`, `exec_prefix`, `libdir` and
+    /// `includedir`, so they may reference them.
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Settings under `package.metadata.capi.library`
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct LibraryMetadata {
+    /// Library name, defaults to the crate name.
+    pub name: Option<String>,
+    /// Library version, defaults to the crate version.
+    #[serde(deserialize_with = "deserialize_version")]
+    #[schemars(with = "Option<String>")]
+    pub version: Option<Version>,
+    /// Library types to build, defaults to both. Overridden by
+    /// `--library-type`.
+    pub types: Option<Vec<LibraryType>>,
+}
+
+/// Kind of library produced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LibraryType {
+    Staticlib,
+    Cdylib,
+}
+
+impl LibraryType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LibraryType::Staticlib => "staticlib",
+            LibraryType::Cdylib => "cdylib",
+        }
+    }
+}
+
+fn deserialize_version<'de, D>(deserializer: D) -> Result<Option<Version>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let s: String = serde::Deserialize::deserialize(deserializer)?;
+    Version::parse(&s)
+        .map(Some)
+        .map_err(|e| D::Error::custom(format!("invalid version `{}`: {}", s, e)))
+}
+
+/// Deserialize the capi table found at `key`.
+///
+/// Unknown and deprecated keys are reported in `warnings`, type errors
+/// carry the full key path.
+pub fn parse(
+    key: &str,
+    value: toml::Value,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<CApiMetadata> {
+    let mut unused = Vec::new();
+
+    let metadata: CApiMetadata = {
+        let mut track = |path: serde_ignored::Path| unused.push(path.to_string());
+        let de = serde_ignored::Deserializer::new(value, &mut track);
+
+        serde_path_to_error::deserialize(de).map_err(|e| {
+            let path = e.path().to_string();
+            if path == "." {
+                anyhow::anyhow!("{}: {}", key, e.inner())
+            } else {
+                anyhow::anyhow!("{}.{}: {}", key, path, e.inner())
+            }
+        })?
+    };
+
+    for unused_key in unused {
+        warnings.push(format!("unused manifest key: {}.{}", key, unused_key));
+    }
+
+    if metadata.header_name.is_some() {
+        warnings.push(format!(
+            "{0}.header_name is deprecated, use {0}.header.name instead",
+            key
+        ));
+    }
+
+    for platform in metadata.target.keys() {
+        platform
+            .parse::<Platform>()
+            .map_err(|e| anyhow::anyhow!("{}.target.{}: {}", key, platform, e))?;
+    }
+
+    Ok(metadata)
+}
+
+/// Sections a `target` table may override
+const TARGET_SECTIONS: &[&str] = &["header", "pkg_config", "library"];
+
+/// Merge the `target` tables matching the target triple `name` and its
+/// `cfg` values over the base settings, in key order.
+///
+/// Only the `header`, `pkg_config` and `library` sections of each table are
+/// merged, the other keys are dropped after [`parse`] reported them. The
+/// `target` table is removed from `value`, the applied overrides are
+/// returned along with their key.
+pub fn apply_target_overrides(
+    key: &str,
+    value: &mut toml::Value,
+    name: &str,
+    cfg: &[Cfg],
+) -> anyhow::Result<Vec<(String, toml::Value)>> {
+    let targets = match value.as_table_mut().and_then(|t| t.remove("target")) {
+        Some(toml::Value::Table(targets)) => targets,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut applied = Vec::new();
+
+    for (platform, overrides) in targets {
+        let matches = platform
+            .parse::<Platform>()
+            .map_err(|e| anyhow::anyhow!("{}.target.{}: {}", key, platform, e))?
+            .matches(name, cfg);
+
+        if matches {
+            let overrides = toml::Value::Table(
+                overrides
+                    .as_table()
+                    .into_iter()
+                    .flatten()
+                    .filter(|(section, _)| TARGET_SECTIONS.contains(&section.as_str()))
+                    .map(|(section, value)| (section.clone(), value.clone()))
+                    .collect(),
+            );
+            merge(value, overrides.clone());
+            applied.push((platform, overrides));
+        }
+    }
+
+    Ok(applied)
+}
+
+/// Record `origin` for every value set in `value`, keyed by its dotted path.
+pub fn record_origins(value: &toml::Value, origin: &str, origins: &mut BTreeMap<String, String>) {
+    fn walk(value: &toml::Value, path: &str, origin: &str, origins: &mut BTreeMap<String, String>) {
+        match value {
+            toml::Value::Table(table) => {
+                for (key, value) in table {
+                    let path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    walk(value, &path, origin, origins);
+                }
+            }
+            _ => {
+                origins.insert(path.to_owned(), origin.to_owned());
+            }
+        }
+    }
+
+    walk(value, "", origin, origins);
+}
+
+/// Merge `overrides` over `base`.
+///
+/// Tables are merged recursively, any other value in `overrides` replaces
+/// the one in `base`.
+pub fn merge(base: &mut toml::Value, overrides: toml::Value) {
+    match (base, overrides) {
+        (toml::Value::Table(base), toml::Value::Table(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// JSON schema describing the `package.metadata.capi` section
+pub fn schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(CApiMetadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(s: &str) -> (anyhow::Result<CApiMetadata>, Vec<String>) {
+        let mut warnings = Vec::new();
+        let value = s.parse::<toml::Value>().unwrap();
+        (parse(CAPI_KEY, value, &mut warnings), warnings)
+    }
+
+    #[test]
+    fn valid() {
+        let (metadata, warnings) = parse_str(
+            r#"
+            min_version = "0.6.10"
+            [header]
+            name = "foo"
+            subdirectory = false
+            [header.version]
+            check = true
+            [header.cbindgen]
+            language = "C"
+            [header.cbindgen.export]
+            prefix = "Foo"
+            [pkg_config]
+            requires = ["glib-2.0 >= 2.40"]
+            url = "https://example.com"
+            relocatable = true
+            static_cflags = ["-DFOO_STATIC"]
+            libs = ["-llog"]
+            libs_private = ["-lm"]
+            cflags = ["-DFOO_API"]
+            [pkg_config.variables]
+            plugindir = "${libdir}/foo"
+            [library]
+            version = "1.2.3"
+            "#,
+        );
+        let metadata = metadata.unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(metadata.header.name.as_deref(), Some("foo"));
+        assert_eq!(metadata.header.subdirectory, Some(false));
+        assert_eq!(metadata.header.version.check, Some(true));
+        assert_eq!(
+            metadata.header.cbindgen.unwrap()["export"]["prefix"].as_str(),
+            Some("Foo")
+        );
+        assert_eq!(metadata.pkg_config.requires, vec!["glib-2.0 >= 2.40"]);
+        assert_eq!(
+            metadata.pkg_config.url.as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(metadata.pkg_config.relocatable, Some(true));
+        assert_eq!(metadata.pkg_config.static_cflags, vec!["-DFOO_STATIC"]);
+        assert_eq!(metadata.pkg_config.libs, vec!["-llog"]);
+        assert_eq!(metadata.pkg_config.libs_private, vec!["-lm"]);
+        assert_eq!(metadata.pkg_config.cflags, vec!["-DFOO_API"]);
+        assert_eq!(metadata.pkg_config.variables["plugindir"], "${libdir}/foo");
+        assert_eq!(metadata.library.version, Some(Version::new(1, 2, 3)));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let (metadata, warnings) = parse_str(
+            r#"
+            [heder]
+            name = "foo"
+            [header]
+            nmae = "foo"
+            "#,
+        );
+
+        assert!(metadata.is_ok());
+        assert_eq!(
+            warnings,
+            vec![
+                "unused manifest key: package.metadata.capi.header.nmae",
+                "unused manifest key: package.metadata.capi.heder",
+            ]
+        );
+    }
+
+    #[test]
+    fn deprecated_keys() {
+        let (metadata, warnings) = parse_str(r#"header_name = "foo""#);
+
+        assert_eq!(metadata.unwrap().header_name.as_deref(), Some("foo"));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("header_name is deprecated"));
+    }
+
+    #[test]
+    fn header_generator() {
+        let (metadata, _) = parse_str(
+            r#"
+            [header]
+            generator = "cbindgen-cli"
+            "#,
+        );
+        assert_eq!(
+            metadata.unwrap().header.generator,
+            Some(HeaderGenerator::Builtin(BuiltinGenerator::CbindgenCli))
+        );
+
+        let (metadata, _) = parse_str(
+            r#"
+            [header]
+            generator = { command = ["cargo", "test", "--features", "headers"], output = "foo.h" }
+            "#,
+        );
+        assert_eq!(
+            metadata.unwrap().header.generator,
+            Some(HeaderGenerator::Command {
+                command: vec![
+                    "cargo".into(),
+                    "test".into(),
+                    "--features".into(),
+                    "headers".into()
+                ],
+                output: Some("foo.h".into()),
+            })
+        );
+
+        let (metadata, _) = parse_str(
+            r#"
+            [header]
+            generator = "cbindgen-git"
+            "#,
+        );
+        assert!(metadata.is_err());
+    }
+
+    #[test]
+    fn header_files() {
+        let (metadata, warnings) = parse_str(
+            r#"
+            [header]
+            umbrella = true
+            [[header.files]]
+            name = "core"
+            exclude = ["Codec"]
+            [[header.files]]
+            name = "codec.h"
+            module = "codec"
+            "#,
+        );
+        let header = metadata.unwrap().header;
+
+        assert!(warnings.is_empty());
+        assert_eq!(header.umbrella, Some(true));
+        assert_eq!(header.files.len(), 2);
+        assert_eq!(header.files[0].exclude, vec!["Codec".to_string()]);
+        assert_eq!(header.files[1].module.as_deref(), Some("codec"));
+
+        let (metadata, _) = parse_str(
+            r#"
+            [[header.files]]
+            module = "codec"
+            "#,
+        );
+        assert!(metadata.is_err());
+    }
+
+    #[test]
+    fn type_errors() {
+        let (metadata, _) = parse_str(
+            r#"
+            [header]
+            subdirectory = "yes"
+            "#,
+        );
+        let err = metadata.unwrap_err().to_string();
+
+        assert!(err.starts_with("package.metadata.capi.header.subdirectory: "));
+        assert!(err.contains("expected a boolean"));
+
+        let (metadata, _) = parse_str(
+            r#"
+            [library]
+            version = "1.2"
+            "#,
+        );
+        let err = metadata.unwrap_err().to_string();
+
+        assert!(err.starts_with("package.metadata.capi.library.version: "));
+    }
+
+    #[test]
+    fn merge_workspace() {
+        let mut base = r#"
+            min_version = "0.6.10"
+            [header]
+            subdirectory = false
+            generation = true
+            [pkg_config]
+            description = "shared description"
+            "#
+        .parse::<toml::Value>()
+        .unwrap();
+        let overrides = r#"
+            [header]
+            generation = false
+            [library]
+            name = "foo"
+            "#
+        .parse::<toml::Value>()
+        .unwrap();
+
+        merge(&mut base, overrides);
+
+        let mut warnings = Vec::new();
+        let metadata = parse(CAPI_KEY, base, &mut warnings).unwrap();
+
+        assert_eq!(metadata.min_version, Some(Version::new(0, 6, 10)));
+        assert_eq!(metadata.header.subdirectory, Some(false));
+        assert_eq!(metadata.header.generation, Some(false));
+        assert_eq!(
+            metadata.pkg_config.description.as_deref(),
+            Some("shared description")
+        );
+        assert_eq!(metadata.library.name.as_deref(), Some("foo"));
+    }
+
+    #[test]
+    fn target_overrides() {
+        let mut value = r#"
+            [library]
+            name = "foo"
+            [target.'cfg(windows)'.library]
+            name = "foo-win"
+            [target.'cfg(target_env = "musl")'.library]
+            types = ["staticlib"]
+            [target.x86_64-unknown-linux-musl.pkg_config]
+            name = "foo-musl"
+            [target.'cfg(unix)']
+            header_name = "bar"
+            min_version = "99.0.0"
+            "#
+        .parse::<toml::Value>()
+        .unwrap();
+
+        let cfg = ["unix", r#"target_os="linux""#, r#"target_env="musl""#]
+            .iter()
+            .map(|c| c.parse::<Cfg>().unwrap())
+            .collect::<Vec<_>>();
+
+        let applied =
+            apply_target_overrides(CAPI_KEY, &mut value, "x86_64-unknown-linux-musl", &cfg)
+                .unwrap();
+
+        let mut origins = BTreeMap::new();
+        for (platform, overrides) in applied.iter() {
+            record_origins(overrides, platform, &mut origins);
+        }
+
+        assert_eq!(
+            origins.get("library.types").map(String::as_str),
+            Some(r#"cfg(target_env = "musl")"#)
+        );
+        assert_eq!(
+            origins.get("pkg_config.name").map(String::as_str),
+            Some("x86_64-unknown-linux-musl")
+        );
+        assert_eq!(origins.len(), 2);
+
+        let metadata = parse(CAPI_KEY, value, &mut Vec::new()).unwrap();
+
+        assert!(metadata.target.is_empty());
+        assert_eq!(metadata.header_name, None);
+        assert_eq!(metadata.min_version, None);
+        assert_eq!(metadata.library.name.as_deref(), Some("foo"));
+        assert_eq!(metadata.library.types, Some(vec![LibraryType::Staticlib]));
+        assert_eq!(metadata.pkg_config.name.as_deref(), Some("foo-musl"));
+    }
+
+    #[test]
+    fn invalid_target() {
+        let (metadata, _) = parse_str(
+            r#"
+            [target.'cfg(windows'.library]
+            name = "foo"
+            "#,
+        );
+        let err = metadata.unwrap_err().to_string();
+
+        assert!(err.starts_with("package.metadata.capi.target.cfg(windows: "));
+    }
+}
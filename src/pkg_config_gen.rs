@@ -2,17 +2,28 @@
 
 use crate::build::CApiConfig;
 use crate::install::InstallPaths;
-use std::path::PathBuf;
+use crate::Error;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+/// Variables pkg-config defines by itself
+const BUILTIN_VARIABLES: &[&str] = &["pcfiledir", "pc_sysrootdir", "pc_top_builddir"];
+
+/// Operators allowed between a package name and its version
+const VERSION_OPERATORS: &[&str] = &["=", "!=", "<", ">", "<=", ">="];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct PkgConfig {
     prefix: PathBuf,
     exec_prefix: PathBuf,
     includedir: PathBuf,
     libdir: PathBuf,
+    variables: BTreeMap<String, String>,
 
     name: String,
     description: String,
+    url: Option<String>,
     version: String,
 
     requires: Vec<String>,
@@ -22,6 +33,7 @@ pub struct PkgConfig {
     libs_private: Vec<String>,
 
     cflags: Vec<String>,
+    cflags_private: Vec<String>,
 
     conflicts: Vec<String>,
 }
@@ -37,26 +49,42 @@ impl PkgConfig {
     ///
     /// Name: $name
     /// Description: $description
+    /// URL: $url
     /// Version: $version
+    /// Requires: $requires
+    /// Requires.private: $requires_private
+    /// Conflicts: $conflicts
     /// Cflags: -I${includedir}/$name
+    /// Cflags.private: $static_cflags
     /// Libs: -L${libdir} -l$name
     ///
     pub fn new(name: &str, capi_config: &CApiConfig) -> Self {
         PkgConfig {
             name: capi_config.pkg_config.name.clone(),
-            description: capi_config.pkg_config.description.clone(),
+            description: capi_config
+                .pkg_config
+                .description
+                .lines()
+                .map(str::trim)
+                .collect::<Vec<_>>()
+                .join(" "),
+            url: capi_config.pkg_config.url.clone(),
             version: capi_config.pkg_config.version.clone(),
 
             prefix: "/usr/local".into(),
             exec_prefix: "${prefix}".into(),
             includedir: "${prefix}/include".into(),
             libdir: "${exec_prefix}/lib".into(),
+            variables: capi_config.pkg_config.variables.clone(),
 
-            libs: vec![format!("-L{} -l{}", "${libdir}", capi_config.library.name)],
+            libs: vec![
+                String::from("-L${libdir}"),
+                format!("-l{}", capi_config.library.name),
+            ],
             libs_private: Vec::new(),
 
-            requires: Vec::new(),
-            requires_private: Vec::new(),
+            requires: capi_config.pkg_config.requires.clone(),
+            requires_private: capi_config.pkg_config.requires_private.clone(),
 
             cflags: vec![if capi_config.header.subdirectory {
                 format!("-I{}/{}", "${includedir}", name)
@@ -64,26 +92,144 @@ impl PkgConfig {
                 String::from("-I${includedir}")
             }],
 
-            conflicts: Vec::new(),
+            cflags_private: capi_config
+                .pkg_config
+                .static_cflags
+                .iter()
+                .flat_map(|flag| split_args(flag))
+                .collect(),
+
+            conflicts: capi_config.pkg_config.conflicts.clone(),
         }
     }
 
     pub(crate) fn from_workspace(
         name: &str,
         install_paths: &InstallPaths,
-        args: &structopt::clap::ArgMatches<'_>,
         capi_config: &CApiConfig,
-    ) -> Self {
+    ) -> anyhow::Result<Self> {
         let mut pc = PkgConfig::new(name, capi_config);
 
-        pc.prefix = install_paths.prefix.clone();
-        // TODO: support exec_prefix
-        if args.is_present("includedir") {
-            pc.includedir = install_paths.includedir.clone();
+        let pkg_config = &capi_config.pkg_config;
+        for lib in pkg_config.libs.iter() {
+            pc.add_lib(lib);
+        }
+        for lib in pkg_config.libs_private.iter() {
+            pc.add_lib_private(lib);
+        }
+        for flag in pkg_config.cflags.iter() {
+            pc.add_cflag(flag);
+        }
+
+        // The header subdirectory is part of the install includedir and is
+        // added by `Cflags`
+        let includedir = if capi_config.header.subdirectory {
+            install_paths
+                .includedir
+                .parent()
+                .unwrap_or(&install_paths.includedir)
+        } else {
+            &install_paths.includedir
+        };
+
+        if capi_config.pkg_config.relocatable {
+            let prefix = &install_paths.prefix;
+            let exec_prefix = &install_paths.exec_prefix;
+
+            let pcfiledir = install_paths
+                .pkgconfigdir
+                .strip_prefix(prefix)
+                .map_err(|_| {
+                    anyhow::anyhow!(
+                        "A relocatable pkg-config file must be installed under the prefix {}, not in {}",
+                        prefix.display(),
+                        install_paths.pkgconfigdir.display()
+                    )
+                })?;
+
+            pc.prefix = std::iter::once("${pcfiledir}")
+                .chain(pcfiledir.components().map(|_| ".."))
+                .collect::<Vec<_>>()
+                .join("/")
+                .into();
+            pc.exec_prefix = relative_dir(exec_prefix, &[("${prefix}", prefix)]);
+            pc.libdir = relative_dir(
+                &install_paths.libdir,
+                &[("${exec_prefix}", exec_prefix), ("${prefix}", prefix)],
+            );
+            pc.includedir = relative_dir(includedir, &[("${prefix}", prefix)]);
+        } else {
+            pc.prefix = install_paths.prefix.clone();
+            if install_paths.is_explicit("exec_prefix") {
+                pc.exec_prefix = install_paths.exec_prefix.clone();
+            }
+            if install_paths.is_explicit("includedir") {
+                pc.includedir = includedir.to_owned();
+            }
+            if install_paths.is_explicit("libdir") {
+                pc.libdir = install_paths.libdir.clone();
+            }
         }
-        if args.is_present("libdir") {
-            pc.libdir = install_paths.libdir.clone();
+
+        Ok(pc)
+    }
+
+    /// The pkg-config file to use the library straight from the build
+    /// directory: the libraries are in `libdir` and the headers in
+    /// `include_dirs`, the generated ones first.
+    pub(crate) fn uninstalled(&self, libdir: &Path, include_dirs: &[&Path]) -> Self {
+        let mut pc = self.clone();
+
+        pc.prefix = libdir.to_owned();
+        pc.exec_prefix = "${prefix}".into();
+        pc.libdir = libdir.to_owned();
+        if let Some(dir) = include_dirs.first() {
+            pc.includedir = dir.to_path_buf();
         }
+
+        // The headers are not in a subdirectory in the build directory
+        let cflags = std::mem::take(&mut pc.cflags);
+        pc.cflags = std::iter::once(String::from("-I${includedir}"))
+            .chain(
+                include_dirs
+                    .iter()
+                    .skip(1)
+                    .map(|dir| format!("-I{}", dir.display())),
+            )
+            .chain(
+                cflags
+                    .into_iter()
+                    .filter(|flag| !flag.starts_with("-I${includedir}")),
+            )
+            .collect();
+
+        pc
+    }
+
+    /// The pkg-config file to link statically without `--static`: the
+    /// `-l<lib_name>` entry points at the `static_lib` archive in `libdir`,
+    /// and the private libraries, flags and requirements are public.
+    pub(crate) fn to_static(&self, lib_name: &str, static_lib: &str) -> Self {
+        let mut pc = self.clone();
+        let lib = format!("-l{}", lib_name);
+
+        let mut libs_private = std::mem::take(&mut pc.libs_private);
+        pc.libs = pc
+            .libs
+            .into_iter()
+            .filter(|entry| !libs_private.contains(entry))
+            .map(|entry| {
+                if entry == lib {
+                    format!("${{libdir}}/{}", static_lib)
+                } else {
+                    entry
+                }
+            })
+            .collect();
+        pc.libs.append(&mut libs_private);
+        pc.requires.append(&mut pc.requires_private);
+        pc.cflags.append(&mut pc.cflags_private);
+
         pc
     }
 
@@ -93,125 +239,869 @@ impl PkgConfig {
     }
 
     pub fn set_libs<S: AsRef<str>>(&mut self, lib: S) -> &mut Self {
-        let lib = lib.as_ref().to_owned();
-        self.libs.clear();
-        self.libs.push(lib);
+        self.libs = split_args(lib.as_ref());
         self
     }
 
     pub fn add_lib<S: AsRef<str>>(&mut self, lib: S) -> &mut Self {
-        let lib = lib.as_ref().to_owned();
-        self.libs.push(lib);
+        self.libs.extend(split_args(lib.as_ref()));
         self
     }
 
     pub fn set_libs_private<S: AsRef<str>>(&mut self, lib: S) -> &mut Self {
-        let lib = lib.as_ref().to_owned();
-        self.libs.clear();
-        self.libs.push(lib);
+        self.libs_private = split_args(lib.as_ref());
         self
     }
 
     pub fn add_lib_private<S: AsRef<str>>(&mut self, lib: S) -> &mut Self {
-        let lib = lib.as_ref().to_owned();
-        self.libs_private.push(lib);
+        self.libs_private.extend(split_args(lib.as_ref()));
         self
     }
 
     pub fn set_cflags<S: AsRef<str>>(&mut self, flag: S) -> &mut Self {
-        let flag = flag.as_ref().to_owned();
-        self.libs.clear();
-        self.libs.push(flag);
+        self.cflags = split_args(flag.as_ref());
         self
     }
 
     pub fn add_cflag<S: AsRef<str>>(&mut self, flag: S) -> &mut Self {
-        let flag = flag.as_ref();
-        self.libs.push(flag.to_owned());
+        self.cflags.extend(split_args(flag.as_ref()));
         self
     }
 
+    /// Parse a `.pc` file, `parse(&pc.render())` gives back `pc`
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut pc = PkgConfig::default();
+        let mut variables = BTreeSet::new();
+        let mut fields = BTreeSet::new();
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let lineno = index + 1;
+            let pos = match line.find(&['=', ':'][..]) {
+                Some(pos) => pos,
+                None => anyhow::bail!(
+                    "line {}: expected a `name=value` variable or a `Field: value` line",
+                    lineno
+                ),
+            };
+            let key = line[..pos].trim();
+            let value = line[pos + 1..].trim();
+
+            if line[pos..].starts_with('=') {
+                if !variables.insert(key) {
+                    anyhow::bail!("line {}: variable `{}` is defined twice", lineno, key);
+                }
+
+                match key {
+                    "prefix" => pc.prefix = value.into(),
+                    "exec_prefix" => pc.exec_prefix = value.into(),
+                    "libdir" => pc.libdir = value.into(),
+                    "includedir" => pc.includedir = value.into(),
+                    _ => {
+                        pc.variables.insert(key.to_owned(), value.to_owned());
+                    }
+                }
+                continue;
+            }
+
+            if !fields.insert(key) {
+                anyhow::bail!("line {}: field `{}` is defined twice", lineno, key);
+            }
+
+            match key {
+                "Name" => pc.name = value.to_owned(),
+                "Description" => pc.description = value.to_owned(),
+                "URL" => pc.url = Some(value.to_owned()),
+                "Version" => pc.version = value.to_owned(),
+                "Requires" => pc.requires = split_packages(value),
+                "Requires.private" => pc.requires_private = split_packages(value),
+                "Conflicts" => pc.conflicts = split_packages(value),
+                "Libs" => pc.libs = split_args(value),
+                "Libs.private" => pc.libs_private = split_args(value),
+                "Cflags" => pc.cflags = split_args(value),
+                "Cflags.private" => pc.cflags_private = split_args(value),
+                _ => anyhow::bail!("line {}: unknown field `{}`", lineno, key),
+            }
+        }
+
+        Ok(pc)
+    }
+
+    /// The custom variables, each after the ones it references.
+    ///
+    /// pkg-config expands the variables when they are defined, so a
+    /// variable must come after the ones it references. The variables in
+    /// a reference cycle are kept in name order and reported by
+    /// [`validate`](Self::validate).
+    fn ordered_variables(&self) -> Vec<(&String, &String)> {
+        let mut ordered: Vec<(&String, &String)> = Vec::new();
+        let mut pending: Vec<(&String, &String)> = self.variables.iter().collect();
+
+        loop {
+            let before = pending.len();
+            pending.retain(|&(name, value)| {
+                let ready = references(value).unwrap_or_default().iter().all(|r| {
+                    !self.variables.contains_key(*r)
+                        || ordered.iter().any(|(defined, _)| defined.as_str() == *r)
+                });
+                if ready {
+                    ordered.push((name, value));
+                }
+                !ready
+            });
+            if pending.is_empty() || pending.len() == before {
+                break;
+            }
+        }
+        ordered.extend(pending);
+
+        ordered
+    }
+
+    /// Check the variable references, the syntax of the fields and the
+    /// versions, returning the problems found.
+    ///
+    /// An empty `prefix`, `exec_prefix`, `libdir` or `includedir` is
+    /// considered undefined.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let dirs = [
+            ("prefix", &self.prefix),
+            ("exec_prefix", &self.exec_prefix),
+            ("libdir", &self.libdir),
+            ("includedir", &self.includedir),
+        ];
+        let variables = dirs
+            .iter()
+            .filter(|(_, dir)| !dir.as_os_str().is_empty())
+            .map(|(name, dir)| (name.to_string(), dir.to_string_lossy().into_owned()))
+            .chain(
+                self.ordered_variables()
+                    .into_iter()
+                    .map(|(name, value)| (name.clone(), value.clone())),
+            )
+            .collect::<Vec<_>>();
+
+        // Variables are expanded when defined, so they may only reference
+        // the ones defined before them
+        let mut defined: BTreeSet<&str> = BUILTIN_VARIABLES.iter().copied().collect();
+        for (name, value) in variables.iter() {
+            let what = format!("variable `{}`", name);
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            {
+                problems.push(format!("{} has an invalid name", what));
+            }
+            check_value(&what, value, &defined, &mut problems);
+            defined.insert(name);
+        }
+
+        let single = [
+            ("Name", Some(&self.name)),
+            ("Description", Some(&self.description)),
+            ("URL", self.url.as_ref()),
+            ("Version", Some(&self.version)),
+        ];
+        for (field, value) in single.iter() {
+            if let Some(value) = value {
+                check_value(&format!("`{}`", field), value, &defined, &mut problems);
+            }
+        }
+
+        if self.name.is_empty() {
+            problems.push(String::from("`Name` is empty"));
+        }
+        if !is_version(&self.version) {
+            problems.push(format!(
+                "`Version` `{}` is not a valid version",
+                self.version
+            ));
+        }
+        if let Some(url) = self.url.as_ref() {
+            if url.is_empty() || url.contains(char::is_whitespace) {
+                problems.push(format!("`URL` `{}` is not a valid URL", url));
+            }
+        }
+
+        let packages = [
+            ("Requires", &self.requires),
+            ("Requires.private", &self.requires_private),
+            ("Conflicts", &self.conflicts),
+        ];
+        for (field, entries) in packages.iter() {
+            for entry in entries.iter() {
+                let what = format!("`{}` entry `{}`", field, entry);
+                check_value(&what, entry, &defined, &mut problems);
+                if let Err(problem) = check_package(entry) {
+                    problems.push(format!("{} {}", what, problem));
+                }
+            }
+        }
+
+        let args = [
+            ("Libs", &self.libs),
+            ("Libs.private", &self.libs_private),
+            ("Cflags", &self.cflags),
+            ("Cflags.private", &self.cflags_private),
+        ];
+        for (field, entries) in args.iter() {
+            for entry in entries.iter() {
+                let what = format!("`{}` entry `{}`", field, entry);
+                check_value(&what, entry, &defined, &mut problems);
+                if entry.is_empty() || entry.contains(char::is_whitespace) {
+                    problems.push(format!("{} is not a single argument", what));
+                }
+            }
+        }
+
+        problems
+    }
+
     pub fn render(&self) -> String {
         let mut base = format!(
             "prefix={}
 exec_prefix={}
 libdir={}
 includedir={}
-
-Name: {}
-Description: {}
-Version: {}
-Libs: {}
-Cflags: {}",
+",
             self.prefix.display(),
             self.exec_prefix.display(),
             self.libdir.display(),
             self.includedir.display(),
-            self.name,
-            self.description,
-            self.version,
-            self.libs.join(" "),
-            self.cflags.join(" "),
         );
 
-        if !self.libs_private.is_empty() {
-            base.push_str(&format!(
-                "
-Libs.private: {}",
-                self.libs_private.join(" "),
-            ));
+        for (name, value) in self.ordered_variables() {
+            base.push_str(&format!("{}={}\n", name, value));
         }
 
-        if !self.requires.is_empty() {
-            base.push_str(&format!(
-                "
-Requires: {}",
-                self.requires.join(", ")
-            ));
+        base.push_str(&format!(
+            "
+Name: {}
+Description: {}
+",
+            self.name, self.description,
+        ));
+
+        if let Some(url) = self.url.as_ref() {
+            base.push_str(&format!("URL: {}\n", url));
         }
-        /*
-        Requires: libavresample >= 4.0.0, libavutil >= 56.8.0
-        Requires.private:
-        Conflicts:
-        Libs.private:
 
-                ).to_owned()
-        */
+        base.push_str(&format!("Version: {}\n", self.version));
 
-        base.push_str("\n");
+        let fields = [
+            ("Requires", self.requires.join(", ")),
+            ("Requires.private", self.requires_private.join(", ")),
+            ("Conflicts", self.conflicts.join(", ")),
+            ("Libs", self.libs.join(" ")),
+            ("Libs.private", self.libs_private.join(" ")),
+            ("Cflags", self.cflags.join(" ")),
+            ("Cflags.private", self.cflags_private.join(" ")),
+        ];
+
+        for (field, value) in fields.iter() {
+            // Libs and Cflags are always present, even if empty
+            if !value.is_empty() || *field == "Libs" || *field == "Cflags" {
+                base.push_str(&format!("{}: {}\n", field, value));
+            }
+        }
 
         base
     }
 }
 
+/// Parse and validate the pkg-config file at `path`
+pub fn check_pc_file(path: &Path) -> anyhow::Result<()> {
+    let content = std::fs::read_to_string(path)?;
+
+    let problems = match PkgConfig::parse(&content) {
+        Ok(pc) => pc.validate(),
+        Err(err) => vec![err.to_string()],
+    };
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidPkgConfig {
+            path: path.to_owned(),
+            problems,
+        }
+        .into())
+    }
+}
+
+/// `path` relative to the first of the `(variable, directory)` pairs it lies
+/// under, e.g. `${prefix}/lib`, or `path` itself
+fn relative_dir(path: &Path, bases: &[(&str, &Path)]) -> PathBuf {
+    for (var, base) in bases.iter() {
+        if let Ok(rel) = path.strip_prefix(base) {
+            return std::iter::once(Cow::from(*var))
+                .chain(rel.components().map(|c| c.as_os_str().to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join("/")
+                .into();
+        }
+    }
+
+    path.to_owned()
+}
+
+fn split_args(value: &str) -> Vec<String> {
+    value.split_whitespace().map(String::from).collect()
+}
+
+/// Parse a `Requires`-like list in `(name, op, version)` entries as
+/// pkg-config does: the entries are separated by commas or whitespace and
+/// the operator ends the name, with or without whitespace around it.
+fn parse_packages(value: &str) -> Vec<(&str, &str, &str)> {
+    let is_separator = |c: char| c == ',' || c.is_whitespace();
+    let is_operator = |c: char| c == '<' || c == '>' || c == '=' || c == '!';
+
+    let mut entries = Vec::new();
+    let mut rest = value;
+
+    loop {
+        rest = rest.trim_start_matches(is_separator);
+        if rest.is_empty() {
+            break;
+        }
+
+        let end = rest
+            .find(|c| is_separator(c) || is_operator(c))
+            .unwrap_or(rest.len());
+        let name = &rest[..end];
+        rest = rest[end..].trim_start();
+
+        let (mut op, mut version) = ("", "");
+        if rest.starts_with(is_operator) {
+            let end = rest.find(|c| !is_operator(c)).unwrap_or(rest.len());
+            op = &rest[..end];
+            rest = rest[end..].trim_start();
+
+            let end = rest.find(is_separator).unwrap_or(rest.len());
+            version = &rest[..end];
+            rest = &rest[end..];
+        }
+
+        entries.push((name, op, version));
+    }
+
+    entries
+}
+
+/// Split a `Requires`-like list in `name [op version]` entries
+fn split_packages(value: &str) -> Vec<String> {
+    parse_packages(value)
+        .into_iter()
+        .map(|(name, op, version)| {
+            if op.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{} {} {}", name, op, version)
+            }
+        })
+        .collect()
+}
+
+/// The variable references in `value`, failing on an unterminated one
+fn references(value: &str) -> Result<Vec<&str>, String> {
+    let mut refs = Vec::new();
+    let mut rest = value;
+
+    while let Some(pos) = rest.find('$') {
+        rest = &rest[pos + 1..];
+        if rest.starts_with('$') {
+            // `$$` is a literal `$`
+            rest = &rest[1..];
+        } else if rest.starts_with('{') {
+            match rest.find('}') {
+                Some(end) => {
+                    refs.push(&rest[1..end]);
+                    rest = &rest[end + 1..];
+                }
+                None => return Err(String::from("has an unterminated variable reference")),
+            }
+        }
+    }
+
+    Ok(refs)
+}
+
+fn check_value(what: &str, value: &str, defined: &BTreeSet<&str>, problems: &mut Vec<String>) {
+    if value.contains('\n') {
+        problems.push(format!("{} spans multiple lines", what));
+    }
+
+    match references(value) {
+        Ok(refs) => {
+            for name in refs.into_iter().filter(|name| !defined.contains(name)) {
+                problems.push(format!("{} references undefined variable `{}`", what, name));
+            }
+        }
+        Err(problem) => problems.push(format!("{} {}", what, problem)),
+    }
+}
+
+fn is_version(version: &str) -> bool {
+    !version.is_empty()
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-_+~".contains(c))
+}
+
+/// Check a `name [op version]` entry
+fn check_package(entry: &str) -> Result<(), String> {
+    match parse_packages(entry).as_slice() {
+        [("", _, _)] => Err(String::from("has no package name")),
+        [(_, "", _)] => Ok(()),
+        [(_, op, _)] if !VERSION_OPERATORS.contains(op) => {
+            Err(format!("has an invalid operator `{}`", op))
+        }
+        [(_, _, "")] => Err(String::from("is not in the `name [op version]` form")),
+        [(_, _, version)] if !is_version(version) => {
+            Err(format!("has an invalid version `{}`", version))
+        }
+        [_] => Ok(()),
+        _ => Err(String::from("is not in the `name [op version]` form")),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::metadata::{BuiltinGenerator, HeaderGenerator, LibraryType};
     use semver::Version;
 
-    #[test]
-    fn simple() {
-        let mut pkg = PkgConfig::new(
-            "foo",
-            &CApiConfig {
-                header: crate::build::HeaderCApiConfig {
-                    name: "foo".into(),
-                    subdirectory: true,
-                    generation: true,
-                },
-                pkg_config: crate::build::PkgConfigCApiConfig {
-                    name: "foo".into(),
-                    description: "".into(),
-                    version: "0.1".into(),
-                },
-                library: crate::build::LibraryCApiConfig {
-                    name: "foo".into(),
+    fn capi_config() -> CApiConfig {
+        CApiConfig {
+            header: crate::build::HeaderCApiConfig {
+                name: "foo".into(),
+                subdirectory: true,
+                generation: true,
+                version: crate::build::HeaderVersionCApiConfig {
+                    prefix: "FOO".into(),
                     version: Version::parse("0.1.0").unwrap(),
+                    string: false,
+                    packed: false,
+                    check: false,
+                    functions: false,
                 },
+                feature_macros: false,
+                cbindgen: None,
+                generator: HeaderGenerator::Builtin(BuiltinGenerator::Bundled),
+                files: Vec::new(),
+                umbrella: false,
+                extra: Vec::new(),
+                variables: Default::default(),
             },
-        );
+            pkg_config: crate::build::PkgConfigCApiConfig {
+                name: "foo".into(),
+                description: "".into(),
+                version: "0.1".into(),
+                requires: Vec::new(),
+                requires_private: Vec::new(),
+                conflicts: Vec::new(),
+                url: None,
+                libs: Vec::new(),
+                libs_private: Vec::new(),
+                cflags: Vec::new(),
+                relocatable: false,
+                static_cflags: Vec::new(),
+                static_pc: false,
+                variables: Default::default(),
+            },
+            library: crate::build::LibraryCApiConfig {
+                name: "foo".into(),
+                version: Version::parse("0.1.0").unwrap(),
+                types: vec![LibraryType::Staticlib, LibraryType::Cdylib],
+            },
+        }
+    }
+
+    #[test]
+    fn simple() {
+        let mut pkg = PkgConfig::new("foo", &capi_config());
         pkg.add_lib("-lbar").add_cflag("-DFOO");
 
         println!("{:?}\n{}", pkg, pkg.render());
     }
+
+    #[test]
+    fn metadata() {
+        let mut capi_config = capi_config();
+        let pkg_config = &mut capi_config.pkg_config;
+        pkg_config.description = "The foo library".into();
+        pkg_config.requires = vec!["glib-2.0 >= 2.40".into(), "gio-2.0".into()];
+        pkg_config.requires_private = vec!["zlib".into()];
+        pkg_config.conflicts = vec!["foo-legacy".into()];
+        pkg_config.url = Some("https://example.com/foo".into());
+        pkg_config
+            .variables
+            .insert("plugindir".into(), "${libdir}/foo".into());
+
+        let pkg = PkgConfig::new("foo", &capi_config);
+
+        assert_eq!(
+            pkg.render(),
+            "prefix=/usr/local
+exec_prefix=${prefix}
+libdir=${exec_prefix}/lib
+includedir=${prefix}/include
+plugindir=${libdir}/foo
+
+Name: foo
+Description: The foo library
+URL: https://example.com/foo
+Version: 0.1
+Requires: glib-2.0 >= 2.40, gio-2.0
+Requires.private: zlib
+Conflicts: foo-legacy
+Libs: -L${libdir} -lfoo
+Cflags: -I${includedir}/foo
+"
+        );
+    }
+
+    fn full() -> PkgConfig {
+        let mut capi_config = capi_config();
+        let pkg_config = &mut capi_config.pkg_config;
+        pkg_config.description = "The foo library".into();
+        pkg_config.requires = vec!["glib-2.0 >= 2.40".into(), "gio-2.0".into()];
+        pkg_config.requires_private = vec!["zlib".into()];
+        pkg_config.conflicts = vec!["foo-legacy < 1.0".into()];
+        pkg_config.url = Some("https://example.com/foo".into());
+        pkg_config.static_cflags = vec!["-DFOO_STATIC -DFOO_NO_DLLIMPORT".into()];
+        pkg_config
+            .variables
+            .insert("plugindir".into(), "${libdir}/foo".into());
+
+        let mut pc = PkgConfig::new("foo", &capi_config);
+        pc.add_lib_private("-lpthread -ldl").add_cflag("-DFOO_API");
+        pc
+    }
+
+    #[test]
+    fn setters() {
+        let mut pc = PkgConfig::new("foo", &capi_config());
+
+        pc.set_cflags("-DFOO").add_cflag("-DBAR -DBAZ");
+        assert_eq!(pc.cflags, vec!["-DFOO", "-DBAR", "-DBAZ"]);
+        assert_eq!(pc.libs, vec!["-L${libdir}", "-lfoo"]);
+
+        pc.set_libs("-lfoo").add_lib("-lm");
+        assert_eq!(pc.libs, vec!["-lfoo", "-lm"]);
+
+        pc.add_lib_private("-lpthread").set_libs_private("-ldl");
+        assert_eq!(pc.libs_private, vec!["-ldl"]);
+        assert_eq!(pc.libs, vec!["-lfoo", "-lm"]);
+
+        pc.add_lib("");
+        assert_eq!(pc.libs, vec!["-lfoo", "-lm"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let pc = full();
+        assert_eq!(PkgConfig::parse(&pc.render()).unwrap(), pc);
+
+        let pc = PkgConfig::new("foo", &capi_config());
+        assert_eq!(PkgConfig::parse(&pc.render()).unwrap(), pc);
+    }
+
+    #[test]
+    fn parse_fields() {
+        let pc = PkgConfig::parse(
+            "# comment
+prefix=/usr
+exec_prefix=${prefix}
+libdir=${exec_prefix}/lib64
+includedir=${prefix}/include
+datadir=${prefix}/share
+
+Name: foo
+Description: The foo library: fast
+URL: https://example.com/foo
+Version: 1.2.3
+Requires: glib-2.0>= 2.40 gio-2.0,gobject-2.0 = 2.40 a>=1 b >=1,c>=1
+Requires.private: zlib
+Conflicts: foo-legacy
+Libs: -L${libdir}  -lfoo
+Libs.private: -lm
+Cflags: -I${includedir}/foo -DFOO
+",
+        )
+        .unwrap();
+
+        assert_eq!(pc.prefix, PathBuf::from("/usr"));
+        assert_eq!(pc.exec_prefix, PathBuf::from("${prefix}"));
+        assert_eq!(pc.libdir, PathBuf::from("${exec_prefix}/lib64"));
+        assert_eq!(pc.includedir, PathBuf::from("${prefix}/include"));
+        assert_eq!(pc.variables["datadir"], "${prefix}/share");
+        assert_eq!(pc.name, "foo");
+        assert_eq!(pc.description, "The foo library: fast");
+        assert_eq!(pc.url.as_deref(), Some("https://example.com/foo"));
+        assert_eq!(pc.version, "1.2.3");
+        assert_eq!(
+            pc.requires,
+            vec![
+                "glib-2.0 >= 2.40",
+                "gio-2.0",
+                "gobject-2.0 = 2.40",
+                "a >= 1",
+                "b >= 1",
+                "c >= 1"
+            ]
+        );
+        assert_eq!(pc.requires_private, vec!["zlib"]);
+        assert_eq!(pc.conflicts, vec!["foo-legacy"]);
+        assert_eq!(pc.libs, vec!["-L${libdir}", "-lfoo"]);
+        assert_eq!(pc.libs_private, vec!["-lm"]);
+        assert_eq!(pc.cflags, vec!["-I${includedir}/foo", "-DFOO"]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |content: &str| PkgConfig::parse(content).unwrap_err().to_string();
+
+        assert_eq!(
+            err("Name: foo\nLibs -lfoo\n"),
+            "line 2: expected a `name=value` variable or a `Field: value` line"
+        );
+        assert_eq!(
+            err("Name: foo\nName: bar\n"),
+            "line 2: field `Name` is defined twice"
+        );
+        assert_eq!(
+            err("libdir=/usr/lib\nlibdir=/usr/lib64\n"),
+            "line 2: variable `libdir` is defined twice"
+        );
+        assert_eq!(err("Cflag: -DFOO\n"), "line 1: unknown field `Cflag`");
+    }
+
+    #[test]
+    fn validate_valid() {
+        assert!(full().validate().is_empty());
+        assert!(PkgConfig::new("foo", &capi_config()).validate().is_empty());
+
+        let mut pc = full();
+        pc.variables
+            .insert("pkgdir".into(), "${pcfiledir}/$$HOME".into());
+        assert!(pc.validate().is_empty());
+
+        // pkg-config does not need whitespace around the operators
+        let mut pc = full();
+        pc.requires = vec!["glib-2.0>=2.40".into(), "a>= 1".into(), "a >=1".into()];
+        assert!(pc.validate().is_empty());
+    }
+
+    #[test]
+    fn validate_variables() {
+        let mut pc = full();
+        pc.variables.insert("a".into(), "${plugindir}/a".into());
+        pc.variables.insert("b.c".into(), "${b".into());
+        pc.variables.insert("bad name".into(), "x".into());
+        pc.variables.insert("c".into(), "${d}".into());
+        pc.variables.insert("d".into(), "${c}".into());
+        pc.libdir = PathBuf::new();
+
+        assert_eq!(
+            pc.validate(),
+            vec![
+                "variable `b.c` has an unterminated variable reference",
+                "variable `bad name` has an invalid name",
+                "variable `plugindir` references undefined variable `libdir`",
+                "variable `c` references undefined variable `d`",
+                "`Libs` entry `-L${libdir}` references undefined variable `libdir`",
+            ]
+        );
+    }
+
+    #[test]
+    fn variables_order() {
+        let mut pc = full();
+        pc.variables.insert("a".into(), "${b}/a".into());
+        pc.variables.insert("b".into(), "${plugindir}/b".into());
+
+        let rendered = pc.render();
+        let pos = |var: &str| rendered.find(&format!("\n{}=", var)).unwrap();
+        assert!(pos("plugindir") < pos("b"));
+        assert!(pos("b") < pos("a"));
+        assert!(pc.validate().is_empty());
+    }
+
+    #[test]
+    fn validate_fields() {
+        let mut pc = full();
+        pc.name = String::new();
+        pc.description = "two\nlines".into();
+        pc.url = Some("https://example.com/ foo".into());
+        pc.version = "1.0 beta".into();
+        pc.requires = vec!["glib-2.0 => 2.40".into(), "gio-2.0 >= 2.x!".into()];
+        pc.requires_private = vec!["zlib >=".into()];
+        pc.conflicts = vec![">= 1.0".into(), "foo 1.0".into()];
+        pc.libs_private = vec!["-lm -ldl".into()];
+        pc.cflags = vec!["-I${incdir}".into()];
+
+        assert_eq!(
+            pc.validate(),
+            vec![
+                "`Description` spans multiple lines",
+                "`Name` is empty",
+                "`Version` `1.0 beta` is not a valid version",
+                "`URL` `https://example.com/ foo` is not a valid URL",
+                "`Requires` entry `glib-2.0 => 2.40` has an invalid operator `=>`",
+                "`Requires` entry `gio-2.0 >= 2.x!` has an invalid version `2.x!`",
+                "`Requires.private` entry `zlib >=` is not in the `name [op version]` form",
+                "`Conflicts` entry `>= 1.0` has no package name",
+                "`Conflicts` entry `foo 1.0` is not in the `name [op version]` form",
+                "`Libs.private` entry `-lm -ldl` is not a single argument",
+                "`Cflags` entry `-I${incdir}` references undefined variable `incdir`",
+            ]
+        );
+    }
+
+    #[test]
+    fn uninstalled() {
+        let mut pc = full();
+        pc.add_cflag("-DFOO");
+        let pc = pc.uninstalled(
+            Path::new("/src/foo/target/release"),
+            &[
+                Path::new("/src/foo/target/release"),
+                Path::new("/src/foo/include"),
+            ],
+        );
+
+        assert_eq!(pc.prefix, PathBuf::from("/src/foo/target/release"));
+        assert_eq!(pc.exec_prefix, PathBuf::from("${prefix}"));
+        assert_eq!(pc.libdir, PathBuf::from("/src/foo/target/release"));
+        assert_eq!(pc.includedir, PathBuf::from("/src/foo/target/release"));
+        assert_eq!(
+            pc.cflags,
+            vec![
+                "-I${includedir}",
+                "-I/src/foo/include",
+                "-DFOO_API",
+                "-DFOO"
+            ]
+        );
+        assert_eq!(pc.libs, vec!["-L${libdir}", "-lfoo"]);
+        assert!(pc.validate().is_empty());
+    }
+
+    fn install_paths(prefix: &str, exec_prefix: &str, libdir: &str) -> InstallPaths {
+        let prefix = PathBuf::from(prefix);
+        let libdir = PathBuf::from(libdir);
+
+        InstallPaths {
+            destdir: "/".into(),
+            exec_prefix: exec_prefix.into(),
+            includedir: prefix.join("include").join("foo"),
+            bindir: prefix.join("bin"),
+            pkgconfigdir: libdir.join("pkgconfig"),
+            libdir,
+            prefix,
+            origins: ["exec_prefix", "libdir", "includedir"]
+                .iter()
+                .map(|key| (*key, crate::install::PathOrigin::CommandLine))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn from_workspace() {
+        let paths = install_paths("/opt/foo", "/opt/foo/x86_64", "/opt/foo/x86_64/lib");
+        let pc = PkgConfig::from_workspace("foo", &paths, &capi_config()).unwrap();
+
+        assert_eq!(pc.prefix, PathBuf::from("/opt/foo"));
+        assert_eq!(pc.exec_prefix, PathBuf::from("/opt/foo/x86_64"));
+        assert_eq!(pc.libdir, PathBuf::from("/opt/foo/x86_64/lib"));
+        assert_eq!(pc.includedir, PathBuf::from("/opt/foo/include"));
+        assert_eq!(pc.cflags, vec!["-I${includedir}/foo"]);
+
+        let mut capi_config = capi_config();
+        capi_config.pkg_config.libs = vec!["-llog".into()];
+        capi_config.pkg_config.libs_private = vec!["-lm -ldl".into()];
+        capi_config.pkg_config.cflags = vec!["-DFOO_ANDROID".into()];
+        let pc = PkgConfig::from_workspace("foo", &paths, &capi_config).unwrap();
+
+        let rendered = pc.render();
+        assert!(rendered.contains("\nLibs: -L${libdir} -lfoo -llog\n"));
+        assert!(rendered.contains("\nLibs.private: -lm -ldl\n"));
+        assert!(rendered.contains("\nCflags: -I${includedir}/foo -DFOO_ANDROID\n"));
+    }
+
+    #[test]
+    fn relocatable() {
+        let mut capi_config = capi_config();
+        capi_config.pkg_config.relocatable = true;
+
+        let paths = install_paths("/opt/foo", "/opt/foo", "/opt/foo/lib64");
+        let pc = PkgConfig::from_workspace("foo", &paths, &capi_config).unwrap();
+
+        assert_eq!(pc.prefix, PathBuf::from("${pcfiledir}/../.."));
+        assert_eq!(pc.exec_prefix, PathBuf::from("${prefix}"));
+        assert_eq!(pc.libdir, PathBuf::from("${exec_prefix}/lib64"));
+        assert_eq!(pc.includedir, PathBuf::from("${prefix}/include"));
+        assert!(pc.validate().is_empty());
+
+        let paths = install_paths("/opt/foo", "/opt/foo/x86_64", "/opt/foo/x86_64/lib");
+        let pc = PkgConfig::from_workspace("foo", &paths, &capi_config).unwrap();
+
+        assert_eq!(pc.prefix, PathBuf::from("${pcfiledir}/../../.."));
+        assert_eq!(pc.exec_prefix, PathBuf::from("${prefix}/x86_64"));
+        assert_eq!(pc.libdir, PathBuf::from("${exec_prefix}/lib"));
+
+        let paths = install_paths("/opt/foo", "/opt/foo", "/usr/lib");
+        assert!(PkgConfig::from_workspace("foo", &paths, &capi_config).is_err());
+    }
+
+    #[test]
+    fn static_flags() {
+        let pc = full();
+        assert_eq!(
+            pc.cflags_private,
+            vec!["-DFOO_STATIC", "-DFOO_NO_DLLIMPORT"]
+        );
+        assert!(pc
+            .render()
+            .contains("\nCflags.private: -DFOO_STATIC -DFOO_NO_DLLIMPORT\n"));
+
+        let pc = pc.to_static("foo", "libfoo.a");
+        assert_eq!(pc.requires, vec!["glib-2.0 >= 2.40", "gio-2.0", "zlib"]);
+        assert!(pc
+            .render()
+            .contains("\nLibs: -L${libdir} ${libdir}/libfoo.a -lpthread -ldl\n"));
+        assert_eq!(
+            pc.cflags,
+            vec![
+                "-I${includedir}/foo",
+                "-DFOO_API",
+                "-DFOO_STATIC",
+                "-DFOO_NO_DLLIMPORT"
+            ]
+        );
+        assert!(pc.requires_private.is_empty());
+        assert!(pc.libs_private.is_empty());
+        assert!(pc.cflags_private.is_empty());
+        assert!(pc.validate().is_empty());
+    }
+
+    #[test]
+    fn static_only() {
+        // Static-only builds list the native libraries in `Libs` as well
+        let mut pc = full();
+        pc.add_lib("-lpthread -ldl");
+
+        let pc = pc.to_static("foo", "foo.lib");
+        assert!(pc
+            .render()
+            .contains("\nLibs: -L${libdir} ${libdir}/foo.lib -lpthread -ldl\n"));
+    }
 }
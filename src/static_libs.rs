@@ -1,7 +1,7 @@
+use std::env;
 use std::ffi::OsString;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
-use std::{env, str};
 
 use anyhow::Result;
 use regex::Regex;
@@ -28,19 +28,15 @@ pub fn get_static_libs_for_target<T: AsRef<std::ffi::OsStr>>(
         cmd.arg("--target").arg(t);
     }
 
-    let out = cmd.output()?;
+    let out = crate::error::run(&mut cmd)?;
 
     log::info!("native-static-libs check {:?} {:?}", cmd, out);
 
-    if out.status.success() {
-        let re = Regex::new(r"note: native-static-libs: (.+)").unwrap();
-        let s = str::from_utf8(&out.stderr).unwrap();
+    let re = Regex::new(r"note: native-static-libs: (.+)").unwrap();
+    let s = String::from_utf8_lossy(&out.stderr);
 
-        Ok(re
-            .captures(s)
-            .map_or("", |cap| cap.get(1).unwrap().as_str())
-            .to_owned())
-    } else {
-        Err(anyhow::anyhow!("cannot run {:?}", cmd))
-    }
+    Ok(re
+        .captures(&s)
+        .map_or("", |cap| cap.get(1).unwrap().as_str())
+        .to_owned())
 }
@@ -1,6 +1,9 @@
 use std::path::PathBuf;
 
 use anyhow::*;
+use cargo_platform::Cfg;
+use serde::Serializer;
+use serde_derive::Serialize;
 
 use crate::build::CApiConfig;
 
@@ -8,13 +11,23 @@ use crate::build::CApiConfig;
 ///
 /// Because of https://github.com/rust-lang/rust/issues/61558
 /// It uses internally `rustc` to validate the string.
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Target {
     pub arch: String,
     // pub vendor: String,
     pub os: String,
     pub env: String,
+    #[serde(skip)]
     pub verbatim: Option<std::ffi::OsString>,
+    /// Target triple, the host one if no target is specified
+    pub triple: String,
+    /// All the `cfg` values reported by `rustc --print cfg`
+    #[serde(serialize_with = "serialize_cfg")]
+    pub cfg: Vec<Cfg>,
+}
+
+fn serialize_cfg<S: Serializer>(cfg: &[Cfg], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(cfg.iter().map(|c| c.to_string()))
 }
 
 impl Target {
@@ -28,31 +41,55 @@ impl Target {
             cmd.arg("--target").arg(t);
         }
 
-        let out = cmd.output()?;
-        if out.status.success() {
-            fn match_re(re: regex::Regex, s: &str) -> String {
-                re.captures(s)
-                    .map_or("", |cap| cap.get(1).unwrap().as_str())
-                    .to_owned()
-            }
-
-            let arch_re = regex::Regex::new(r#"target_arch="(.+)""#).unwrap();
-            // let vendor_re = regex::Regex::new(r#"target_vendor="(.+)""#).unwrap();
-            let os_re = regex::Regex::new(r#"target_os="(.+)""#).unwrap();
-            let env_re = regex::Regex::new(r#"target_env="(.+)""#).unwrap();
-
-            let s = std::str::from_utf8(&out.stdout).unwrap();
-
-            Ok(Target {
-                arch: match_re(arch_re, s),
-                // vendor: match_re(vendor_re, s),
-                os: match_re(os_re, s),
-                env: match_re(env_re, s),
-                verbatim: target.map(|v| v.as_ref().to_os_string()),
-            })
-        } else {
-            Err(anyhow!("Cannot run {:?}", cmd))
+        let out = crate::error::run(&mut cmd)?;
+
+        fn match_re(re: regex::Regex, s: &str) -> String {
+            re.captures(s)
+                .map_or("", |cap| cap.get(1).unwrap().as_str())
+                .to_owned()
         }
+
+        let arch_re = regex::Regex::new(r#"target_arch="(.+)""#).unwrap();
+        // let vendor_re = regex::Regex::new(r#"target_vendor="(.+)""#).unwrap();
+        let os_re = regex::Regex::new(r#"target_os="(.+)""#).unwrap();
+        let env_re = regex::Regex::new(r#"target_env="(.+)""#).unwrap();
+
+        let s = std::str::from_utf8(&out.stdout)?;
+
+        let cfg = s
+            .lines()
+            .map(|line| line.parse::<Cfg>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let triple = match target.as_ref() {
+            Some(t) => t.as_ref().to_string_lossy().into_owned(),
+            None => Self::host_triple()?,
+        };
+
+        Ok(Target {
+            arch: match_re(arch_re, s),
+            // vendor: match_re(vendor_re, s),
+            os: match_re(os_re, s),
+            env: match_re(env_re, s),
+            verbatim: target.map(|v| v.as_ref().to_os_string()),
+            triple,
+            cfg,
+        })
+    }
+
+    fn host_triple() -> Result<String, anyhow::Error> {
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+        let mut cmd = std::process::Command::new(rustc);
+
+        cmd.arg("-vV");
+
+        let out = crate::error::run(&mut cmd)?;
+        let s = std::str::from_utf8(&out.stdout)?;
+
+        s.lines()
+            .find_map(|line| line.strip_prefix("host: "))
+            .map(String::from)
+            .ok_or_else(|| anyhow!("Cannot find the host triple in {:?} output", cmd))
     }
 
     /// Build a list of linker arguments